@@ -4,7 +4,7 @@ use {
     clap::{Parser, Subcommand, ValueEnum},
     futures::{future::TryFutureExt, sink::SinkExt, stream::StreamExt},
     indicatif::{MultiProgress, ProgressBar, ProgressStyle},
-    log::{error, info},
+    log::{error, info, warn},
     serde_json::{json, Value},
     solana_sdk::{hash::Hash, pubkey::Pubkey, signature::Signature},
     solana_transaction_status::UiTransactionEncoding,
@@ -709,6 +709,7 @@ async fn geyser_subscribe(
                         Some(UpdateOneof::Block(_)) => (&mut pb_blocks_c, &pb_blocks),
                         Some(UpdateOneof::Ping(_)) => (&mut pb_pp_c, &pb_pp),
                         Some(UpdateOneof::Pong(_)) => (&mut pb_pp_c, &pb_pp),
+                        Some(UpdateOneof::QuotaWarning(_)) => (&mut pb_pp_c, &pb_pp),
                         None => {
                             pb_multi.println("update not found in the message")?;
                             break;
@@ -884,6 +885,13 @@ async fn geyser_subscribe(
                             .await?;
                     }
                     Some(UpdateOneof::Pong(_)) => {}
+                    Some(UpdateOneof::QuotaWarning(msg)) => {
+                        warn!(
+                            "quota usage {:.0}% crossed warning threshold {:.0}%",
+                            msg.usage_ratio * 100.0,
+                            msg.threshold * 100.0
+                        );
+                    }
                     None => {
                         error!("update not found in the message");
                         break;
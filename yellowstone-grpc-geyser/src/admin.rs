@@ -0,0 +1,521 @@
+use {
+    crate::{
+        config::ConfigGrpcAdmin,
+        metrics::{self, DebugClientRecord, DebugClientStatuses},
+        quota::quota_checker::QuotaChecker,
+        reload::ConfigReloader,
+        user_connection::connection_manager::{ConnectionManager, ShutdownReason},
+    },
+    log::{error, info},
+    ring::{hmac, rand::SystemRandom},
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::Arc,
+        time::UNIX_EPOCH,
+    },
+    tokio::sync::Notify,
+    tonic::{
+        service::interceptor::interceptor, transport::Server, Code, Request, Response,
+        Result as TonicResult, Status,
+    },
+    yellowstone_grpc_proto::{
+        admin::{
+            geyser_admin_server::{GeyserAdmin, GeyserAdminServer},
+            ClientInfo, DisconnectClientRequest, DisconnectResponse, DisconnectTeamRequest,
+            FilterStats, GetFilterStatsRequest, GetFilterStatsResponse, ListClientsRequest,
+            ListClientsResponse, RecheckQuotaRequest, RecheckQuotaResponse, ReloadConfigRequest,
+            ReloadConfigResponse,
+        },
+        plugin::message::SlotStatus,
+    },
+};
+
+/// Admin token, presented tokens are compared by their HMAC in constant time
+#[derive(Debug, Clone)]
+struct AdminToken {
+    key: hmac::Key,
+    tag: hmac::Tag,
+}
+
+impl AdminToken {
+    fn new(token: &str) -> Self {
+        let key = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+            .expect("failed to generate admin token key");
+        let tag = hmac::sign(&key, token.as_bytes());
+        Self { key, tag }
+    }
+
+    fn verify(&self, token: &[u8]) -> bool {
+        hmac::verify(&self.key, token, self.tag.as_ref()).is_ok()
+    }
+}
+
+/// Operator access to connected clients, combines `ConnectionManager` with the
+/// filters and progress reported through `DebugClientMessage`
+#[derive(Debug)]
+pub struct GeyserAdminService {
+    connection_manager: Arc<ConnectionManager>,
+    debug_clients: Arc<DebugClientStatuses>,
+    quota_checker: Arc<QuotaChecker>,
+    config_reloader: Arc<ConfigReloader>,
+}
+
+impl GeyserAdminService {
+    pub const fn new(
+        connection_manager: Arc<ConnectionManager>,
+        debug_clients: Arc<DebugClientStatuses>,
+        quota_checker: Arc<QuotaChecker>,
+        config_reloader: Arc<ConfigReloader>,
+    ) -> Self {
+        Self {
+            connection_manager,
+            debug_clients,
+            quota_checker,
+            config_reloader,
+        }
+    }
+
+    /// Serves the service on `config.address` until `shutdown` is notified
+    pub async fn serve(self, config: ConfigGrpcAdmin, shutdown: Arc<Notify>) {
+        info!("start admin server: {}", config.address);
+        let x_token = AdminToken::new(&config.x_token);
+        let result = Server::builder()
+            .layer(interceptor(move |request: Request<()>| {
+                match request.metadata().get("x-token") {
+                    Some(token) if x_token.verify(token.as_bytes()) => Ok(request),
+                    _ => Err(Status::unauthenticated("No valid auth token")),
+                }
+            }))
+            .add_service(GeyserAdminServer::new(self))
+            .serve_with_shutdown(config.address, shutdown.notified())
+            .await;
+        if let Err(error) = result {
+            error!("admin server failed: {error}");
+        }
+    }
+
+    async fn records(&self) -> TonicResult<HashMap<usize, DebugClientRecord>> {
+        let records = self
+            .debug_clients
+            .get_records()
+            .await
+            .map_err(|error| Status::internal(error.to_string()))?;
+        Ok(records
+            .into_iter()
+            .map(|record| (record.id, record))
+            .collect())
+    }
+
+    fn reason(reason: String) -> ShutdownReason {
+        if reason.is_empty() {
+            ShutdownReason::new(Code::Cancelled, "disconnected by admin")
+        } else {
+            ShutdownReason::new(Code::Cancelled, reason)
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl GeyserAdmin for GeyserAdminService {
+    async fn list_clients(
+        &self,
+        request: Request<ListClientsRequest>,
+    ) -> TonicResult<Response<ListClientsResponse>> {
+        let team_id = request.into_inner().team_id;
+        let connections = self.connection_manager.list_connections(team_id.as_deref());
+        let mut records = self.records().await?;
+        let processed_slot = metrics::slot_plugin_status(SlotStatus::Processed);
+
+        let clients = connections
+            .into_iter()
+            .map(|info| {
+                let record = records.remove(&info.client_id);
+                let filter = record
+                    .as_ref()
+                    .map(|record| serde_json::to_string(&record.filter))
+                    .transpose()
+                    .map_err(|error| Status::internal(error.to_string()))?;
+                Ok(ClientInfo {
+                    client_id: info.client_id as u64,
+                    team_id: info.team_id.clone(),
+                    app_id: info.app_id.clone(),
+                    endpoint: info.endpoint.clone(),
+                    network: info.network.clone(),
+                    connected_at_ms: info
+                        .connected_at
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64,
+                    filter,
+                    processed_slot: record.as_ref().map_or(0, |record| record.processed_slot),
+                    lag: record.as_ref().map_or(0, |record| {
+                        processed_slot.saturating_sub(record.processed_slot)
+                    }),
+                    queue_depth: record.map_or(0, |record| record.queue_depth as u64),
+                })
+            })
+            .collect::<TonicResult<_>>()?;
+        Ok(Response::new(ListClientsResponse { clients }))
+    }
+
+    async fn disconnect_client(
+        &self,
+        request: Request<DisconnectClientRequest>,
+    ) -> TonicResult<Response<DisconnectResponse>> {
+        let request = request.into_inner();
+        let client_id = usize::try_from(request.client_id)
+            .map_err(|_| Status::invalid_argument("invalid client_id"))?;
+        let disconnected = self
+            .connection_manager
+            .shutdown_connection(client_id, Self::reason(request.reason));
+        if !disconnected {
+            return Err(Status::not_found(format!("client #{client_id} not found")));
+        }
+        info!("admin disconnected client #{client_id}");
+        Ok(Response::new(DisconnectResponse { disconnected: 1 }))
+    }
+
+    async fn disconnect_team(
+        &self,
+        request: Request<DisconnectTeamRequest>,
+    ) -> TonicResult<Response<DisconnectResponse>> {
+        let request = request.into_inner();
+        let disconnected = self
+            .connection_manager
+            .shutdown_team(&request.team_id, Self::reason(request.reason));
+        info!(
+            "admin disconnected {disconnected} clients of team {}",
+            request.team_id
+        );
+        Ok(Response::new(DisconnectResponse {
+            disconnected: disconnected as u64,
+        }))
+    }
+
+    async fn get_filter_stats(
+        &self,
+        request: Request<GetFilterStatsRequest>,
+    ) -> TonicResult<Response<GetFilterStatsResponse>> {
+        let team_id = request.into_inner().team_id;
+        let connections = self.connection_manager.list_connections(team_id.as_deref());
+        let records = self.records().await?;
+
+        let mut clients = 0;
+        let mut stats = BTreeMap::<&'static str, FilterStats>::new();
+        for record in connections
+            .iter()
+            .filter_map(|info| records.get(&info.client_id))
+        {
+            clients += 1;
+            for (filter_type, filters) in record.filter.get_metrics() {
+                if filter_type == "all" {
+                    continue;
+                }
+                let entry = stats.entry(filter_type).or_insert_with(|| FilterStats {
+                    filter_type: filter_type.to_owned(),
+                    ..Default::default()
+                });
+                if filters > 0 {
+                    entry.clients += 1;
+                }
+                entry.filters += filters as u64;
+            }
+        }
+
+        Ok(Response::new(GetFilterStatsResponse {
+            clients,
+            filters: stats.into_values().collect(),
+        }))
+    }
+
+    async fn recheck_quota(
+        &self,
+        request: Request<RecheckQuotaRequest>,
+    ) -> TonicResult<Response<RecheckQuotaResponse>> {
+        let summary = self
+            .quota_checker
+            .check_now(request.into_inner().team_ids)
+            .await;
+        info!(
+            "admin quota recheck: {} checked, {} capped, {} failed",
+            summary.checked, summary.capped, summary.failed
+        );
+        Ok(Response::new(RecheckQuotaResponse {
+            checked: summary.checked as u64,
+            capped: summary.capped as u64,
+            failed: summary.failed as u64,
+        }))
+    }
+
+    async fn reload_config(
+        &self,
+        _request: Request<ReloadConfigRequest>,
+    ) -> TonicResult<Response<ReloadConfigResponse>> {
+        let result = self.config_reloader.reload().await.map_err(|error| {
+            error!("admin config reload failed: {error:?}");
+            Status::failed_precondition(format!("{error:#}"))
+        })?;
+        info!(
+            "admin reloaded config, applied: {:?}, not reloadable: {:?}",
+            result.applied, result.not_reloadable
+        );
+        Ok(Response::new(ReloadConfigResponse {
+            applied: result.applied,
+            not_reloadable: result.not_reloadable,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            metrics::DebugClientMessage,
+            quota::{memory_backend::MemoryQuotaBackend, quota_warner::QuotaWarner},
+            user_connection::{
+                connection_manager::{ConnectionInfo, StreamLimits},
+                connection_token::ConnectionToken,
+            },
+        },
+        serde_json::json,
+        std::{
+            path::Path,
+            time::{Duration, SystemTime},
+        },
+        tokio::sync::mpsc,
+        yellowstone_grpc_proto::{
+            plugin::filter::{limits::FilterLimits, name::FilterNames, Filter},
+            prelude::{
+                SubscribeRequest, SubscribeRequestFilterAccounts, SubscribeRequestFilterSlots,
+            },
+        },
+    };
+
+    struct TestAdmin {
+        service: GeyserAdminService,
+        tokens: Vec<ConnectionToken>,
+    }
+
+    fn filter(slots: usize, accounts: usize) -> Box<Filter> {
+        let request = SubscribeRequest {
+            slots: (0..slots)
+                .map(|i| (format!("slots{i}"), SubscribeRequestFilterSlots::default()))
+                .collect(),
+            accounts: (0..accounts)
+                .map(|i| {
+                    (
+                        format!("accounts{i}"),
+                        SubscribeRequestFilterAccounts::default(),
+                    )
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let mut names = FilterNames::new(64, 1024, Duration::from_secs(1));
+        Box::new(Filter::new(&request, &FilterLimits::default(), &mut names).unwrap())
+    }
+
+    /// Clients #1 and #2 of team `a` and #3 of team `b`, #2 didn't send a filter yet
+    async fn create_admin(dir: &Path) -> TestAdmin {
+        let path = dir.join("config.json");
+        let config = json!({
+            "libpath": "",
+            "grpc": {
+                "address": "127.0.0.1:0",
+                "network": { "name": "SOLANA_MAINNET", "skip_genesis_check": true },
+            },
+        });
+        std::fs::write(&path, config.to_string()).unwrap();
+        let (config_reloader, _config) = ConfigReloader::new(&path).unwrap();
+
+        let connection_manager = Arc::new(ConnectionManager::new());
+        let (clients_tx, clients_rx) = mpsc::unbounded_channel();
+        let mut tokens = Vec::new();
+        for (client_id, team_id, filter) in [
+            (1, "a", Some(filter(1, 2))),
+            (2, "a", None),
+            (3, "b", Some(filter(1, 0))),
+        ] {
+            let info = ConnectionInfo {
+                client_id,
+                team_id: team_id.to_owned(),
+                app_id: "app".to_owned(),
+                endpoint: String::new(),
+                network: "SOLANA_MAINNET".to_owned(),
+                connected_at: SystemTime::now(),
+            };
+            tokens.push(
+                connection_manager
+                    .register_connection(info.clone(), StreamLimits::default())
+                    .unwrap(),
+            );
+            if let Some(filter) = filter {
+                clients_tx
+                    .send(DebugClientMessage::Connected {
+                        id: client_id,
+                        info: Arc::new(info),
+                        filter,
+                        queue_capacity: 16,
+                    })
+                    .unwrap();
+            }
+        }
+        let debug_clients = DebugClientStatuses::new(clients_rx);
+        // the status task handles requests and updates in any order
+        while debug_clients.get_records().await.unwrap().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        let live_config = config_reloader.subscribe();
+        let quota_checker = Arc::new(QuotaChecker::new(
+            Arc::clone(&connection_manager),
+            Arc::new(MemoryQuotaBackend::default()),
+            Arc::new(QuotaWarner::new(
+                live_config.clone(),
+                Arc::clone(&connection_manager),
+            )),
+            live_config,
+        ));
+
+        TestAdmin {
+            service: GeyserAdminService::new(
+                connection_manager,
+                debug_clients,
+                quota_checker,
+                Arc::new(config_reloader),
+            ),
+            tokens,
+        }
+    }
+
+    #[test]
+    fn test_admin_token() {
+        let token = AdminToken::new("secret");
+        assert!(token.verify(b"secret"));
+        assert!(!token.verify(b"secret2"));
+        assert!(!token.verify(b"secreT"));
+        assert!(!token.verify(b""));
+    }
+
+    #[tokio::test]
+    async fn test_list_clients() {
+        let dir = tempfile::tempdir().unwrap();
+        let admin = create_admin(dir.path()).await;
+
+        let clients = admin
+            .service
+            .list_clients(Request::new(ListClientsRequest { team_id: None }))
+            .await
+            .unwrap()
+            .into_inner()
+            .clients;
+        let ids = clients
+            .iter()
+            .map(|client| (client.client_id, client.team_id.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [(1, "a"), (2, "a"), (3, "b")]);
+        let filter: serde_json::Value =
+            serde_json::from_str(clients[0].filter.as_deref().unwrap()).unwrap();
+        assert!(filter["slots"]["filters"]["slots0"].is_object());
+        assert_eq!(filter["accounts"]["filters"].as_array().unwrap().len(), 2);
+        assert!(clients[1].filter.is_none());
+
+        let clients = admin
+            .service
+            .list_clients(Request::new(ListClientsRequest {
+                team_id: Some("b".to_owned()),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .clients;
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].client_id, 3);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut admin = create_admin(dir.path()).await;
+
+        let response = admin
+            .service
+            .disconnect_client(Request::new(DisconnectClientRequest {
+                client_id: 2,
+                reason: String::new(),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(response.into_inner().disconnected, 1);
+        let status = admin
+            .service
+            .disconnect_client(Request::new(DisconnectClientRequest {
+                client_id: 4,
+                reason: String::new(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        // #2 is already shutting down
+        let response = admin
+            .service
+            .disconnect_team(Request::new(DisconnectTeamRequest {
+                team_id: "a".to_owned(),
+                reason: "maintenance".to_owned(),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(response.into_inner().disconnected, 1);
+
+        let shutdown = admin
+            .tokens
+            .iter_mut()
+            .map(|token| token.shutdown_rx().borrow().is_shutdown())
+            .collect::<Vec<_>>();
+        assert_eq!(shutdown, [true, true, false]);
+    }
+
+    #[tokio::test]
+    async fn test_filter_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let admin = create_admin(dir.path()).await;
+
+        let stats = |team_id: Option<&str>| {
+            let request = Request::new(GetFilterStatsRequest {
+                team_id: team_id.map(str::to_owned),
+            });
+            let service = &admin.service;
+            async move {
+                let response = service
+                    .get_filter_stats(request)
+                    .await
+                    .unwrap()
+                    .into_inner();
+                let filters = response
+                    .filters
+                    .into_iter()
+                    .filter(|stats| stats.clients > 0)
+                    .map(|stats| (stats.filter_type, stats.clients, stats.filters))
+                    .collect::<Vec<_>>();
+                (response.clients, filters)
+            }
+        };
+
+        assert_eq!(
+            stats(None).await,
+            (
+                2,
+                vec![("accounts".to_owned(), 1, 2), ("slots".to_owned(), 2, 2)]
+            )
+        );
+        assert_eq!(
+            stats(Some("a")).await,
+            (
+                1,
+                vec![("accounts".to_owned(), 1, 2), ("slots".to_owned(), 1, 1)]
+            )
+        );
+    }
+}
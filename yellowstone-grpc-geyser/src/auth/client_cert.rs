@@ -0,0 +1,212 @@
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_OID: u8 = 0x06;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_VERSION: u8 = 0xa0;
+const TAG_EXTENSIONS: u8 = 0xa3;
+const TAG_SAN_DNS: u8 = 0x82;
+const TAG_SAN_URI: u8 = 0x86;
+
+/// 2.5.4.3
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+/// 2.5.29.17
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+struct Der<'a>(&'a [u8]);
+
+impl<'a> Der<'a> {
+    const fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    const fn peek_tag(&self) -> Option<u8> {
+        self.0.first().copied()
+    }
+
+    /// Reads the next element, returns its tag and content
+    fn read(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.0.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        let (len, rest) = if first < 0x80 {
+            (first as usize, rest)
+        } else {
+            let size = (first & 0x7f) as usize;
+            if size == 0 || size > 4 || rest.len() < size {
+                return None;
+            }
+            let (bytes, rest) = rest.split_at(size);
+            let len = bytes
+                .iter()
+                .fold(0usize, |len, &byte| (len << 8) | byte as usize);
+            (len, rest)
+        };
+        if rest.len() < len {
+            return None;
+        }
+        let (content, rest) = rest.split_at(len);
+        self.0 = rest;
+        Some((tag, content))
+    }
+
+    fn read_tag(&mut self, expected: u8) -> Option<&'a [u8]> {
+        match self.read()? {
+            (tag, content) if tag == expected => Some(content),
+            _ => None,
+        }
+    }
+}
+
+/// DNS and URI subject alternative names followed by the subject common names,
+/// an empty list if the certificate can't be parsed. Only the DER structure needed
+/// to reach them is read, the certificate was already verified by rustls
+pub fn cert_names(cert: &[u8]) -> Vec<String> {
+    let mut names = vec![];
+    if parse_names(cert, &mut names).is_none() {
+        // names read before the error can't be trusted either
+        names.clear();
+    }
+    names
+}
+
+fn parse_names(cert: &[u8], names: &mut Vec<String>) -> Option<()> {
+    let mut cert = Der(Der(cert).read_tag(TAG_SEQUENCE)?);
+    let mut tbs = Der(cert.read_tag(TAG_SEQUENCE)?);
+    if tbs.peek_tag() == Some(TAG_VERSION) {
+        tbs.read()?;
+    }
+    tbs.read()?; // serial number
+    tbs.read()?; // signature algorithm
+    tbs.read()?; // issuer
+    tbs.read()?; // validity
+    let subject = tbs.read_tag(TAG_SEQUENCE)?;
+    tbs.read()?; // subject public key info
+
+    while let Some((tag, content)) = tbs.read() {
+        if tag == TAG_EXTENSIONS {
+            parse_extensions(content, names)?;
+        }
+    }
+    parse_subject(subject, names)
+}
+
+fn parse_extensions(extensions: &[u8], names: &mut Vec<String>) -> Option<()> {
+    let mut extensions = Der(Der(extensions).read_tag(TAG_SEQUENCE)?);
+    while !extensions.is_empty() {
+        let mut extension = Der(extensions.read_tag(TAG_SEQUENCE)?);
+        if extension.read_tag(TAG_OID)? != OID_SUBJECT_ALT_NAME {
+            continue;
+        }
+        // skip the optional `critical` flag
+        let value = loop {
+            match extension.read()? {
+                (TAG_OCTET_STRING, value) => break value,
+                _ => continue,
+            }
+        };
+        let mut general_names = Der(Der(value).read_tag(TAG_SEQUENCE)?);
+        while let Some((tag, name)) = general_names.read() {
+            if matches!(tag, TAG_SAN_DNS | TAG_SAN_URI) {
+                if let Ok(name) = std::str::from_utf8(name) {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+fn parse_subject(subject: &[u8], names: &mut Vec<String>) -> Option<()> {
+    let mut subject = Der(subject);
+    while !subject.is_empty() {
+        let mut rdn = Der(subject.read_tag(TAG_SET)?);
+        while !rdn.is_empty() {
+            let mut attribute = Der(rdn.read_tag(TAG_SEQUENCE)?);
+            if attribute.read_tag(TAG_OID)? == OID_COMMON_NAME {
+                // UTF8String, PrintableString and IA5String are all valid UTF-8
+                let (_, value) = attribute.read()?;
+                if let Ok(name) = std::str::from_utf8(value) {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn der(pem: &str) -> Vec<u8> {
+        rustls_pemfile::certs(&mut pem.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap()
+            .to_vec()
+    }
+
+    fn cert_cn() -> Vec<u8> {
+        der(include_str!("../../testdata/client_cert_cn.pem"))
+    }
+
+    #[test]
+    fn test_common_name() {
+        assert_eq!(cert_names(&cert_cn()), ["team-a.example"]);
+    }
+
+    #[test]
+    fn test_subject_alt_names() {
+        // critical SAN after other extensions, the email SAN is ignored
+        let cert = der(include_str!("../../testdata/client_cert_san.pem"));
+        assert_eq!(
+            cert_names(&cert),
+            [
+                "client.example.com",
+                "spiffe://example/team-b",
+                "ignored-cn"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multi_valued_rdn() {
+        let cert = der(include_str!("../../testdata/client_cert_multi_rdn.pem"));
+        assert_eq!(cert_names(&cert), ["first", "second"]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let cert = cert_cn();
+        for len in 0..cert.len() {
+            assert!(cert_names(&cert[..len]).is_empty(), "prefix of {len} bytes");
+        }
+    }
+
+    #[test]
+    fn test_malformed_length() {
+        let cert = cert_cn();
+        assert_eq!(cert[0], TAG_SEQUENCE);
+
+        // indefinite length isn't DER
+        let mut malformed = cert.clone();
+        malformed[1] = 0x80;
+        assert!(cert_names(&malformed).is_empty());
+
+        // more length bytes than supported
+        let mut malformed = cert.clone();
+        malformed[1] = 0x85;
+        assert!(cert_names(&malformed).is_empty());
+
+        // length past the end of the input
+        let mut malformed = vec![TAG_SEQUENCE, 0x84, 0xff, 0xff, 0xff, 0xff];
+        malformed.extend_from_slice(&cert);
+        assert!(cert_names(&malformed).is_empty());
+
+        // any corrupted byte must not panic
+        for index in 0..cert.len() {
+            let mut corrupted = cert.clone();
+            corrupted[index] ^= 0xff;
+            let _ = cert_names(&corrupted);
+        }
+    }
+}
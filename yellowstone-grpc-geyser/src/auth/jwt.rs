@@ -0,0 +1,521 @@
+use {
+    crate::{auth::FileContent, quota::QuotaLimits},
+    base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+    ring::{
+        hmac,
+        signature::{self, RsaPublicKeyComponents, UnparsedPublicKey},
+    },
+    serde::Deserialize,
+    std::{
+        future::Future,
+        path::Path,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+    thiserror::Error,
+};
+
+#[derive(Debug, Error)]
+pub enum JwtError {
+    #[error("malformed token")]
+    Malformed,
+    #[error("unsupported algorithm {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("invalid claims: {0}")]
+    InvalidClaims(serde_json::Error),
+    #[error("token expired")]
+    Expired,
+    #[error("token not valid yet")]
+    NotYetValid,
+    #[error("invalid issuer")]
+    InvalidIssuer,
+    #[error("invalid audience")]
+    InvalidAudience,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    HS256,
+    RS256,
+    EdDSA,
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+/// Key of a JWKS file, `oct` keys are used for HS256, `RSA` for RS256
+/// and `OKP` with the `Ed25519` curve for EdDSA
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kty")]
+enum JwkKey {
+    #[serde(rename = "oct")]
+    Oct { k: String },
+    #[serde(rename = "RSA")]
+    Rsa { n: String, e: String },
+    #[serde(rename = "OKP")]
+    Okp { crv: String, x: String },
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwk {
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(flatten)]
+    key: JwkKey,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug)]
+enum VerifyingKey {
+    Hmac(hmac::Key),
+    Rsa(RsaPublicKeyComponents<Vec<u8>>),
+    Ed25519(Vec<u8>),
+}
+
+impl VerifyingKey {
+    const fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::Hmac(_) => Algorithm::HS256,
+            Self::Rsa(_) => Algorithm::RS256,
+            Self::Ed25519(_) => Algorithm::EdDSA,
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            Self::Hmac(key) => hmac::verify(key, message, signature).is_ok(),
+            Self::Rsa(key) => key
+                .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, signature)
+                .is_ok(),
+            Self::Ed25519(key) => UnparsedPublicKey::new(&signature::ED25519, key)
+                .verify(message, signature)
+                .is_ok(),
+        }
+    }
+}
+
+/// Verifying keys loaded from a JWKS file
+#[derive(Debug, Default)]
+pub struct Jwks {
+    keys: Vec<(Option<String>, VerifyingKey)>,
+}
+
+impl Jwks {
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        Self::parse(&tokio::fs::read_to_string(path).await?)
+    }
+
+    fn parse(data: &str) -> anyhow::Result<Self> {
+        let set: JwkSet = serde_json::from_str(data)?;
+
+        let mut keys = Vec::with_capacity(set.keys.len());
+        for jwk in set.keys {
+            let decode = |value: &str| URL_SAFE_NO_PAD.decode(value);
+            let key = match jwk.key {
+                JwkKey::Oct { k } => {
+                    VerifyingKey::Hmac(hmac::Key::new(hmac::HMAC_SHA256, &decode(&k)?))
+                }
+                JwkKey::Rsa { n, e } => VerifyingKey::Rsa(RsaPublicKeyComponents {
+                    n: decode(&n)?,
+                    e: decode(&e)?,
+                }),
+                JwkKey::Okp { crv, x } => {
+                    anyhow::ensure!(crv == "Ed25519", "unsupported OKP curve {crv}");
+                    VerifyingKey::Ed25519(decode(&x)?)
+                }
+            };
+            keys.push((jwk.kid, key));
+        }
+        Ok(Self { keys })
+    }
+}
+
+impl FileContent for Jwks {
+    const NAME: &'static str = "jwks";
+
+    fn load_file(path: &Path) -> impl Future<Output = anyhow::Result<Self>> + Send {
+        Self::load(path)
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+/// `aud` is either a single audience or a list
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, audience: &str) -> bool {
+        match self {
+            Self::One(value) => value == audience,
+            Self::Many(values) => values.iter().any(|value| value == audience),
+        }
+    }
+}
+
+/// Claims of a client token
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    exp: u64,
+    #[serde(default)]
+    nbf: Option<u64>,
+    #[serde(default)]
+    iss: Option<String>,
+    #[serde(default)]
+    aud: Option<Audience>,
+    pub team_id: String,
+    pub app_id: String,
+    pub network: String,
+    /// Limits of the plan, limits of the team in the quota backend take precedence
+    #[serde(default)]
+    pub limits: Option<QuotaLimits>,
+}
+
+/// What is checked besides the signature
+#[derive(Debug, Clone, Default)]
+pub struct Validation {
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    /// Allowed clock skew for `exp` and `nbf`
+    pub leeway: Duration,
+}
+
+pub fn verify(token: &str, jwks: &Jwks, validation: &Validation) -> Result<Claims, JwtError> {
+    let mut parts = token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(JwtError::Malformed);
+    };
+    let decode = |value: &str| {
+        URL_SAFE_NO_PAD
+            .decode(value)
+            .map_err(|_| JwtError::Malformed)
+    };
+
+    let header: Header =
+        serde_json::from_slice(&decode(header)?).map_err(|_| JwtError::Malformed)?;
+    let algorithm = match header.alg.as_str() {
+        "HS256" => Algorithm::HS256,
+        "RS256" => Algorithm::RS256,
+        "EdDSA" => Algorithm::EdDSA,
+        _ => return Err(JwtError::UnsupportedAlgorithm(header.alg)),
+    };
+
+    // the algorithm must match the key, so an RSA public key can't be used as an HMAC secret
+    let message = signed_part(token);
+    let signature = decode(signature)?;
+    let verified = jwks
+        .keys
+        .iter()
+        .filter(|(kid, key)| {
+            key.algorithm() == algorithm
+                && (header.kid.is_none() || kid.is_none() || *kid == header.kid)
+        })
+        .any(|(_, key)| key.verify(message.as_bytes(), &signature));
+    if !verified {
+        return Err(JwtError::InvalidSignature);
+    }
+
+    let claims: Claims =
+        serde_json::from_slice(&decode(payload)?).map_err(JwtError::InvalidClaims)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let leeway = validation.leeway.as_secs();
+    if claims.exp.saturating_add(leeway) <= now {
+        return Err(JwtError::Expired);
+    }
+    if claims
+        .nbf
+        .is_some_and(|nbf| nbf > now.saturating_add(leeway))
+    {
+        return Err(JwtError::NotYetValid);
+    }
+    if let Some(issuer) = &validation.issuer {
+        if claims.iss.as_ref() != Some(issuer) {
+            return Err(JwtError::InvalidIssuer);
+        }
+    }
+    if let Some(audience) = &validation.audience {
+        if !claims
+            .aud
+            .as_ref()
+            .is_some_and(|aud| aud.contains(audience))
+        {
+            return Err(JwtError::InvalidAudience);
+        }
+    }
+    Ok(claims)
+}
+
+/// `{header}.{payload}` of the token
+fn signed_part(token: &str) -> &str {
+    token.rsplit_once('.').map_or(token, |(signed, _)| signed)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        ring::{
+            rand::SystemRandom,
+            signature::{Ed25519KeyPair, KeyPair},
+        },
+        serde_json::{json, Value},
+    };
+
+    const SECRET: &[u8] = b"test-secret";
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn claims(extra: Value) -> Value {
+        let mut claims = json!({
+            "exp": now() + 600,
+            "team_id": "team",
+            "app_id": "app",
+            "network": "SOLANA_MAINNET",
+        });
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        claims
+    }
+
+    fn encode(value: &Value) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(value).unwrap())
+    }
+
+    fn hs256(header: Value, claims: Value, secret: &[u8]) -> String {
+        let message = format!("{}.{}", encode(&header), encode(&claims));
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        let signature = hmac::sign(&key, message.as_bytes());
+        format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn hs256_jwks(kid: &str, secret: &[u8]) -> Jwks {
+        Jwks::parse(
+            &json!({
+                "keys": [{ "kty": "oct", "kid": kid, "k": URL_SAFE_NO_PAD.encode(secret) }]
+            })
+            .to_string(),
+        )
+        .unwrap()
+    }
+
+    fn validation() -> Validation {
+        Validation {
+            issuer: None,
+            audience: None,
+            leeway: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_valid() {
+        let jwks = hs256_jwks("key1", SECRET);
+        let token = hs256(
+            json!({"alg": "HS256", "kid": "key1"}),
+            claims(json!({})),
+            SECRET,
+        );
+        let claims = verify(&token, &jwks, &validation()).unwrap();
+        assert_eq!(claims.team_id, "team");
+        assert_eq!(claims.app_id, "app");
+        assert_eq!(claims.network, "SOLANA_MAINNET");
+    }
+
+    #[test]
+    fn test_eddsa() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key = pair.public_key().as_ref();
+        let jwks = Jwks::parse(
+            &json!({
+                "keys": [{ "kty": "OKP", "crv": "Ed25519", "x": URL_SAFE_NO_PAD.encode(public_key) }]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let message = format!(
+            "{}.{}",
+            encode(&json!({"alg": "EdDSA"})),
+            encode(&claims(json!({})))
+        );
+        let signature = URL_SAFE_NO_PAD.encode(pair.sign(message.as_bytes()));
+        let token = format!("{message}.{signature}");
+        assert!(verify(&token, &jwks, &validation()).is_ok());
+
+        // the public key used as an HMAC secret
+        let token = hs256(json!({"alg": "HS256"}), claims(json!({})), public_key);
+        assert!(matches!(
+            verify(&token, &jwks, &validation()),
+            Err(JwtError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_bad_signature() {
+        let jwks = hs256_jwks("key1", SECRET);
+        let token = hs256(json!({"alg": "HS256"}), claims(json!({})), b"other-secret");
+        assert!(matches!(
+            verify(&token, &jwks, &validation()),
+            Err(JwtError::InvalidSignature)
+        ));
+
+        // claims changed after signing
+        let token = hs256(json!({"alg": "HS256"}), claims(json!({})), SECRET);
+        let (header, rest) = token.split_once('.').unwrap();
+        let (_, signature) = rest.split_once('.').unwrap();
+        let forged = format!(
+            "{header}.{}.{signature}",
+            encode(&claims(json!({"team_id": "other"})))
+        );
+        assert!(matches!(
+            verify(&forged, &jwks, &validation()),
+            Err(JwtError::InvalidSignature)
+        ));
+
+        // signed by a key with another kid
+        let token = hs256(
+            json!({"alg": "HS256", "kid": "key2"}),
+            claims(json!({})),
+            SECRET,
+        );
+        assert!(matches!(
+            verify(&token, &jwks, &validation()),
+            Err(JwtError::InvalidSignature)
+        ));
+
+        assert!(matches!(
+            verify("header.payload", &jwks, &validation()),
+            Err(JwtError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn test_algorithm() {
+        let jwks = hs256_jwks("key1", SECRET);
+        let token = format!(
+            "{}.{}.",
+            encode(&json!({"alg": "none"})),
+            encode(&claims(json!({})))
+        );
+        assert!(matches!(
+            verify(&token, &jwks, &validation()),
+            Err(JwtError::UnsupportedAlgorithm(alg)) if alg == "none"
+        ));
+
+        // HMAC signature with a header of another algorithm
+        let token = hs256(json!({"alg": "RS256"}), claims(json!({})), SECRET);
+        assert!(matches!(
+            verify(&token, &jwks, &validation()),
+            Err(JwtError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_expired() {
+        let jwks = hs256_jwks("key1", SECRET);
+        let token = hs256(
+            json!({"alg": "HS256"}),
+            claims(json!({"exp": now() - 30})),
+            SECRET,
+        );
+        // within the leeway
+        assert!(verify(&token, &jwks, &validation()).is_ok());
+        let strict = Validation {
+            leeway: Duration::ZERO,
+            ..validation()
+        };
+        assert!(matches!(
+            verify(&token, &jwks, &strict),
+            Err(JwtError::Expired)
+        ));
+    }
+
+    #[test]
+    fn test_not_before() {
+        let jwks = hs256_jwks("key1", SECRET);
+        let token = hs256(
+            json!({"alg": "HS256"}),
+            claims(json!({"nbf": now() + 30})),
+            SECRET,
+        );
+        // within the leeway
+        assert!(verify(&token, &jwks, &validation()).is_ok());
+
+        let token = hs256(
+            json!({"alg": "HS256"}),
+            claims(json!({"nbf": now() + 120})),
+            SECRET,
+        );
+        assert!(matches!(
+            verify(&token, &jwks, &validation()),
+            Err(JwtError::NotYetValid)
+        ));
+    }
+
+    #[test]
+    fn test_issuer_and_audience() {
+        let jwks = hs256_jwks("key1", SECRET);
+        let validation = Validation {
+            issuer: Some("issuer".to_owned()),
+            audience: Some("geyser".to_owned()),
+            ..validation()
+        };
+
+        let token = hs256(
+            json!({"alg": "HS256"}),
+            claims(json!({"iss": "issuer", "aud": ["other", "geyser"]})),
+            SECRET,
+        );
+        assert!(verify(&token, &jwks, &validation).is_ok());
+
+        for claims in [
+            claims(json!({"iss": "other", "aud": "geyser"})),
+            claims(json!({"aud": "geyser"})),
+        ] {
+            let token = hs256(json!({"alg": "HS256"}), claims, SECRET);
+            assert!(matches!(
+                verify(&token, &jwks, &validation),
+                Err(JwtError::InvalidIssuer)
+            ));
+        }
+
+        for claims in [
+            claims(json!({"iss": "issuer", "aud": "other"})),
+            claims(json!({"iss": "issuer", "aud": ["other"]})),
+            claims(json!({"iss": "issuer"})),
+        ] {
+            let token = hs256(json!({"alg": "HS256"}), claims, SECRET);
+            assert!(matches!(
+                verify(&token, &jwks, &validation),
+                Err(JwtError::InvalidAudience)
+            ));
+        }
+    }
+}
@@ -0,0 +1,280 @@
+pub mod client_cert;
+pub mod jwt;
+pub mod tokens;
+
+use {
+    crate::{
+        auth::{
+            client_cert::cert_names,
+            jwt::{Jwks, Validation},
+            tokens::TokenTable,
+        },
+        config::{ConfigAuth, ConfigClientCert},
+        metrics::AUTH_REJECTED,
+        quota::QuotaLimits,
+    },
+    anyhow::Context,
+    log::{error, info},
+    std::{
+        collections::HashMap,
+        future::Future,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex, RwLock},
+        time::SystemTime,
+    },
+    tokio::time::{interval, Duration, MissedTickBehavior},
+    tonic::{metadata::MetadataMap, transport::CertificateDer, Status},
+    yellowstone_grpc_proto::plugin::filter::limits::FilterLimits,
+};
+
+/// Who opened a stream, from a client certificate, an API token, a verified JWT
+/// or trusted proxy headers
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    pub team_id: String,
+    pub app_id: String,
+    pub network: String,
+    /// Limits of the plan from the token
+    pub limits: Option<QuotaLimits>,
+    /// Filter limits of the token, the global `filter_limits` if not set
+    pub filter_limits: Option<Arc<FilterLimits>>,
+}
+
+/// Content of a file that is reloaded once it changes
+trait FileContent: Sized + Send + Sync + 'static {
+    const NAME: &'static str;
+
+    fn load_file(path: &Path) -> impl Future<Output = anyhow::Result<Self>> + Send;
+
+    fn len(&self) -> usize;
+}
+
+impl FileContent for TokenTable {
+    const NAME: &'static str = "token file";
+
+    fn load_file(path: &Path) -> impl Future<Output = anyhow::Result<Self>> + Send {
+        Self::load(path)
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+}
+
+#[derive(Debug)]
+struct WatchedFile<T> {
+    path: PathBuf,
+    modified: Mutex<Option<SystemTime>>,
+    content: RwLock<Arc<T>>,
+}
+
+impl<T: FileContent> WatchedFile<T> {
+    async fn new(path: Option<&PathBuf>) -> anyhow::Result<Option<Self>> {
+        let Some(path) = path else {
+            return Ok(None);
+        };
+        let modified = Self::modified(path).await?;
+        let content = T::load_file(path)
+            .await
+            .with_context(|| format!("failed to load {} {path:?}", T::NAME))?;
+        info!("loaded {} entries from {} {path:?}", content.len(), T::NAME);
+        Ok(Some(Self {
+            path: path.clone(),
+            modified: Mutex::new(modified),
+            content: RwLock::new(Arc::new(content)),
+        }))
+    }
+
+    async fn modified(path: &Path) -> anyhow::Result<Option<SystemTime>> {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .with_context(|| format!("failed to read {} metadata {path:?}", T::NAME))?;
+        Ok(metadata.modified().ok())
+    }
+
+    fn get(&self) -> Arc<T> {
+        Arc::clone(&self.content.read().unwrap())
+    }
+
+    /// Reloads the file if it was modified, on failure keeps the previous content
+    async fn reload_if_changed(&self) {
+        let modified = match Self::modified(&self.path).await {
+            Ok(modified) => modified,
+            Err(error) => {
+                error!("{error:?}");
+                return;
+            }
+        };
+        if modified.is_some() && modified == *self.modified.lock().unwrap() {
+            return;
+        }
+
+        match T::load_file(&self.path).await {
+            Ok(content) => {
+                info!(
+                    "reloaded {} entries from {} {:?}",
+                    content.len(),
+                    T::NAME,
+                    self.path
+                );
+                *self.content.write().unwrap() = Arc::new(content);
+                *self.modified.lock().unwrap() = modified;
+            }
+            Err(error) => error!("failed to reload {} {:?}: {error:?}", T::NAME, self.path),
+        }
+    }
+}
+
+/// Maps a verified client certificate to a team, looks up `x-token` in the token file
+/// or verifies `authorization: Bearer <jwt>` against keys of a JWKS file, the
+/// `x-alchemy-*` headers are only accepted with `trusted_proxy`
+#[derive(Debug)]
+pub struct Authenticator {
+    client_certs: HashMap<String, ConfigClientCert>,
+    tokens: Option<WatchedFile<TokenTable>>,
+    jwks: Option<WatchedFile<Jwks>>,
+    validation: Validation,
+    trusted_proxy: bool,
+}
+
+impl Authenticator {
+    pub async fn new(config: &ConfigAuth) -> anyhow::Result<Self> {
+        let tokens = WatchedFile::new(config.tokens_path.as_ref()).await?;
+        let jwks = WatchedFile::new(config.jwks_path.as_ref()).await?;
+        if tokens.is_none()
+            && jwks.is_none()
+            && config.client_certs.is_empty()
+            && !config.trusted_proxy
+        {
+            error!(
+                "none of tokens_path, jwks_path, client_certs and trusted_proxy is set, all streams will be rejected"
+            );
+        }
+
+        Ok(Self {
+            client_certs: config.client_certs.clone(),
+            tokens,
+            jwks,
+            validation: Validation {
+                issuer: config.issuer.clone(),
+                audience: config.audience.clone(),
+                leeway: config.leeway,
+            },
+            trusted_proxy: config.trusted_proxy,
+        })
+    }
+
+    /// Reloads the token and JWKS files once they change, streams opened with
+    /// a removed token are kept
+    pub async fn run_reload(self: Arc<Self>, reload_interval: Duration) {
+        if self.tokens.is_none() && self.jwks.is_none() {
+            return;
+        }
+        let mut ticker = interval(reload_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Some(tokens) = &self.tokens {
+                tokens.reload_if_changed().await;
+            }
+            if let Some(jwks) = &self.jwks {
+                jwks.reload_if_changed().await;
+            }
+        }
+    }
+
+    /// `peer_certs` are the certificates of a client verified against `client_ca_path`
+    pub fn authenticate(
+        &self,
+        metadata: &MetadataMap,
+        peer_certs: Option<&[CertificateDer<'_>]>,
+    ) -> Result<ClientIdentity, Status> {
+        if let Some(identity) = peer_certs.and_then(|certs| self.cert_identity(certs)) {
+            return Ok(identity);
+        }
+
+        let result = self.identity(metadata);
+        if result.is_err() {
+            AUTH_REJECTED.inc();
+        }
+        result
+    }
+
+    /// A certificate without a configured name only secures the transport,
+    /// the client is authenticated by headers then
+    fn cert_identity(&self, certs: &[CertificateDer<'_>]) -> Option<ClientIdentity> {
+        if self.client_certs.is_empty() {
+            return None;
+        }
+        let cert = certs.first()?;
+        let config = cert_names(cert)
+            .iter()
+            .find_map(|name| self.client_certs.get(name))?;
+        Some(ClientIdentity {
+            team_id: config.team_id.clone(),
+            app_id: config.app_id.clone(),
+            network: config.network.clone(),
+            limits: None,
+            filter_limits: None,
+        })
+    }
+
+    fn identity(&self, metadata: &MetadataMap) -> Result<ClientIdentity, Status> {
+        if let Some(tokens) = &self.tokens {
+            if let Some(token) = metadata.get("x-token") {
+                let table = tokens.get();
+                let entry = token
+                    .to_str()
+                    .ok()
+                    .and_then(|token| table.get(token))
+                    .ok_or_else(|| Status::unauthenticated("invalid x-token"))?;
+                return Ok(ClientIdentity {
+                    team_id: entry.team_id.clone(),
+                    app_id: entry.app_id.clone(),
+                    network: entry.network.clone(),
+                    limits: entry.limits,
+                    filter_limits: entry.filter_limits.clone(),
+                });
+            }
+        }
+
+        let bearer = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        match (bearer, &self.jwks) {
+            (Some(token), Some(jwks)) => {
+                let claims = jwt::verify(token.trim(), &jwks.get(), &self.validation)
+                    .map_err(|error| Status::unauthenticated(format!("invalid token: {error}")))?;
+                Ok(ClientIdentity {
+                    team_id: claims.team_id,
+                    app_id: claims.app_id,
+                    network: claims.network,
+                    limits: claims.limits,
+                    filter_limits: None,
+                })
+            }
+            // a proxy may forward tokens meant for itself
+            (None, _) | (Some(_), None) if self.trusted_proxy => Ok(ClientIdentity {
+                team_id: Self::header(metadata, "x-alchemy-team-id", "team id")?,
+                app_id: Self::header(metadata, "x-alchemy-app-id", "app id")?,
+                network: Self::header(metadata, "x-alchemy-network", "network")?,
+                limits: None,
+                filter_limits: None,
+            }),
+            _ => Err(Status::unauthenticated(
+                "missing or unsupported credentials",
+            )),
+        }
+    }
+
+    fn header(metadata: &MetadataMap, key: &str, name: &str) -> Result<String, Status> {
+        metadata
+            .get(key)
+            .ok_or_else(|| Status::invalid_argument(format!("missing {name}")))?
+            .to_str()
+            .map(ToOwned::to_owned)
+            .map_err(|_| Status::invalid_argument(format!("invalid {name}")))
+    }
+}
@@ -0,0 +1,96 @@
+use {
+    crate::quota::QuotaLimits,
+    anyhow::Context,
+    serde::Deserialize,
+    std::{collections::HashMap, path::Path, sync::Arc},
+    yellowstone_grpc_proto::plugin::filter::limits::FilterLimits,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenConfig {
+    team_id: String,
+    app_id: String,
+    network: String,
+    /// Name of a filter limits profile, the global `filter_limits` if not set
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default)]
+    limits: Option<QuotaLimits>,
+}
+
+/// e.g. `{ "profiles": { "free": { "accounts": { "any": false } }, "enterprise": {} },
+/// "tokens": { "<token>": { "team_id": "..", "app_id": "..", "network": "..", "profile": "free" } } }`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenFile {
+    #[serde(default)]
+    profiles: HashMap<String, FilterLimits>,
+    tokens: HashMap<String, TokenConfig>,
+}
+
+/// Identity and limits of an API token
+#[derive(Debug)]
+pub struct TokenEntry {
+    pub team_id: String,
+    pub app_id: String,
+    pub network: String,
+    pub filter_limits: Option<Arc<FilterLimits>>,
+    pub limits: Option<QuotaLimits>,
+}
+
+/// API tokens read from a JSON or TOML file (by extension)
+#[derive(Debug, Default)]
+pub struct TokenTable {
+    tokens: HashMap<String, TokenEntry>,
+}
+
+impl TokenTable {
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read token file {path:?}"))?;
+        let file: TokenFile = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&data).context("failed to parse token file as toml")?,
+            _ => serde_json::from_str(&data).context("failed to parse token file as json")?,
+        };
+
+        let profiles: HashMap<String, Arc<FilterLimits>> = file
+            .profiles
+            .into_iter()
+            .map(|(name, limits)| (name, Arc::new(limits)))
+            .collect();
+        let mut tokens = HashMap::with_capacity(file.tokens.len());
+        for (token, config) in file.tokens {
+            let filter_limits = match &config.profile {
+                Some(profile) => Some(Arc::clone(profiles.get(profile).with_context(|| {
+                    format!("unknown profile {profile} of team {}", config.team_id)
+                })?)),
+                None => None,
+            };
+            tokens.insert(
+                token,
+                TokenEntry {
+                    team_id: config.team_id,
+                    app_id: config.app_id,
+                    network: config.network,
+                    filter_limits,
+                    limits: config.limits,
+                },
+            );
+        }
+        Ok(Self { tokens })
+    }
+
+    pub fn get(&self, token: &str) -> Option<&TokenEntry> {
+        self.tokens.get(token)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+}
@@ -0,0 +1,91 @@
+use {
+    crate::billing::{unix_ms, BillingEvent},
+    std::{collections::HashMap, sync::Arc, time::SystemTime},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct AggregationKey {
+    team_id: String,
+    app_id: String,
+    eth_network: String,
+    eth_method: String,
+    subscription_type: String,
+    log_source: String,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct AggregationTotals {
+    response_content_length: u64,
+    weighted_content_length: u64,
+    message_count: u64,
+}
+
+/// Sums per-connection events by (team, app, network, type) over a window
+#[derive(Debug)]
+pub struct BillingAggregator {
+    instance_id: Arc<str>,
+    sequence: u64,
+    window_start: SystemTime,
+    totals: HashMap<AggregationKey, AggregationTotals>,
+}
+
+impl BillingAggregator {
+    pub fn new(instance_id: Arc<str>) -> Self {
+        Self {
+            instance_id,
+            sequence: 0,
+            window_start: SystemTime::now(),
+            totals: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, event: BillingEvent) {
+        let key = AggregationKey {
+            team_id: event.team_id,
+            app_id: event.app_id,
+            eth_network: event.eth_network,
+            eth_method: event.eth_method,
+            subscription_type: event.subscription_type,
+            log_source: event.log_source,
+        };
+        let totals = self.totals.entry(key).or_default();
+        totals.response_content_length += event.response_content_length;
+        totals.weighted_content_length += event.weighted_content_length;
+        totals.message_count += event.message_count;
+    }
+
+    /// Closes the current window and returns one event per key
+    pub fn drain(&mut self) -> Vec<BillingEvent> {
+        let sequence = self.sequence;
+        let window_end = SystemTime::now();
+        let window_start = std::mem::replace(&mut self.window_start, window_end);
+        self.sequence += 1;
+
+        self.totals
+            .drain()
+            .map(|(key, totals)| BillingEvent {
+                event_id: format!(
+                    "{}:team-{}:{sequence}:{}:{}:{}",
+                    self.instance_id,
+                    key.team_id,
+                    key.app_id,
+                    key.eth_network,
+                    key.subscription_type
+                ),
+                subscription_id: format!("grpc-team-{}", key.team_id),
+                team_id: key.team_id,
+                app_id: key.app_id,
+                eth_method: key.eth_method,
+                eth_network: key.eth_network,
+                subscription_type: key.subscription_type,
+                log_source: key.log_source,
+                response_content_length: totals.response_content_length,
+                message_count: totals.message_count,
+                weighted_content_length: totals.weighted_content_length,
+                sequence,
+                window_start_ms: unix_ms(window_start),
+                window_end_ms: unix_ms(window_end),
+            })
+            .collect()
+    }
+}
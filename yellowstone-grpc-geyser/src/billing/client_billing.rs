@@ -0,0 +1,226 @@
+use {
+    crate::{
+        billing::{unix_ms, BillingEvent, BillingSender},
+        quota::usage_meter::TeamUsage,
+    },
+    log::error,
+    prost::Message as ProstMessage,
+    std::{collections::HashMap, sync::Arc, time::SystemTime},
+    yellowstone_grpc_proto::plugin::filter::message::FilteredUpdate,
+};
+
+/// Path through which a message reached the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BillingSource {
+    Live,
+    Replay,
+    Snapshot,
+}
+
+impl BillingSource {
+    fn subscription_type(self, message_type: &str) -> String {
+        match self {
+            Self::Live => message_type.to_owned(),
+            Self::Replay => format!("{message_type}_replay"),
+            Self::Snapshot => format!("{message_type}_snapshot"),
+        }
+    }
+}
+
+/// Accumulates bytes sent to a single client between billing ticks
+#[derive(Debug)]
+pub struct ClientBilling {
+    instance_id: Arc<str>,
+    id: usize,
+    team_id: String,
+    app_id: String,
+    network: String,
+    billing_tx: BillingSender,
+    /// Set with `usage_metering`
+    usage: Option<Arc<TeamUsage>>,
+    /// Encoded bytes and number of messages by source and message type
+    sent: HashMap<(BillingSource, &'static str), (u64, u64)>,
+    /// Encoded bytes of all messages since the client connected
+    bytes_sent: u64,
+    sequence: u64,
+    window_start: SystemTime,
+}
+
+impl ClientBilling {
+    pub fn new(
+        instance_id: Arc<str>,
+        id: usize,
+        team_id: String,
+        app_id: String,
+        network: String,
+        billing_tx: BillingSender,
+        usage: Option<Arc<TeamUsage>>,
+    ) -> Self {
+        Self {
+            instance_id,
+            id,
+            team_id,
+            app_id,
+            network,
+            billing_tx,
+            usage,
+            sent: HashMap::new(),
+            bytes_sent: 0,
+            sequence: 0,
+            window_start: SystemTime::now(),
+        }
+    }
+
+    pub fn record(&mut self, source: BillingSource, message: &FilteredUpdate) {
+        self.record_sized(source, message, ProstMessage::encoded_len(message) as u64);
+    }
+
+    /// Same as `record` for a message whose encoded length is already known
+    pub fn record_sized(&mut self, source: BillingSource, message: &FilteredUpdate, size: u64) {
+        self.bytes_sent += size;
+        let message_type = message.message.subscription_type();
+        if message_type != "ping" && message_type != "pong" {
+            let sent = self.sent.entry((source, message_type)).or_default();
+            sent.0 += size;
+            sent.1 += 1;
+            if let Some(usage) = &self.usage {
+                usage.record(size, 1);
+            }
+        }
+    }
+
+    pub const fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Emits one event per source and message type for everything recorded since the last flush
+    pub fn flush(&mut self) {
+        let id = self.id;
+        let sequence = self.sequence;
+        let window_end = SystemTime::now();
+        let window_start = std::mem::replace(&mut self.window_start, window_end);
+        self.sequence += 1;
+
+        for ((source, message_type), (size, count)) in self.sent.drain() {
+            let subscription_type = source.subscription_type(message_type);
+            let event = BillingEvent {
+                event_id: format!("{}:{id}:{sequence}:{subscription_type}", self.instance_id),
+                app_id: self.app_id.clone(),
+                team_id: self.team_id.clone(),
+                eth_method: message_type.to_string(),
+                eth_network: self.network.clone(),
+                subscription_id: format!("grpc-client-{id}"),
+                subscription_type,
+                log_source: "grpc".to_string(),
+                response_content_length: size,
+                message_count: count,
+                // weighted by `BillingSender`
+                weighted_content_length: size,
+                sequence,
+                window_start_ms: unix_ms(window_start),
+                window_end_ms: unix_ms(window_end),
+            };
+
+            if let Err(err) = self.billing_tx.try_send(event) {
+                error!("Failed to queue billing event for client #{id}: {err:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::billing::{memory_sink::MemorySink, BillingService, BillingSink, BillingWeights},
+        prost_types::Timestamp,
+        std::time::Duration,
+        yellowstone_grpc_proto::plugin::{
+            filter::message::FilteredUpdateOneof,
+            message::{MessageSlot, SlotStatus},
+        },
+    };
+
+    fn slot(slot: u64) -> FilteredUpdate {
+        FilteredUpdate::new_empty(FilteredUpdateOneof::slot(MessageSlot {
+            slot,
+            parent: None,
+            status: SlotStatus::Processed,
+            dead_error: None,
+            created_at: Timestamp::default(),
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_flush_is_aggregated_per_team() {
+        let sink = Arc::new(MemorySink::default());
+        let (service, _task) = BillingService::new(
+            Arc::clone(&sink) as Arc<dyn BillingSink>,
+            None,
+            "test".into(),
+            16,
+            16,
+            Duration::from_millis(10),
+            Some(Duration::from_millis(10)),
+            BillingWeights::default(),
+        );
+        let usage = Arc::new(TeamUsage::default());
+        let mut clients = (0..2)
+            .map(|id| {
+                ClientBilling::new(
+                    "test".into(),
+                    id,
+                    "team".to_owned(),
+                    "app".to_owned(),
+                    "SOLANA_MAINNET".to_owned(),
+                    service.sender.clone(),
+                    Some(Arc::clone(&usage)),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        clients[0].record_sized(BillingSource::Live, &slot(1), 100);
+        clients[0].record_sized(BillingSource::Replay, &slot(0), 50);
+        clients[0].record_sized(
+            BillingSource::Live,
+            &FilteredUpdate::new_empty(FilteredUpdateOneof::Ping),
+            10,
+        );
+        clients[1].record_sized(BillingSource::Live, &slot(1), 100);
+        assert_eq!(clients[0].bytes_sent(), 160);
+        // pings are not billed
+        assert_eq!(usage.total().bytes, 250);
+        assert_eq!(usage.total().messages, 3);
+        for client in &mut clients {
+            client.flush();
+        }
+
+        let mut events = Vec::new();
+        for _ in 0..500 {
+            events.extend(sink.take_events());
+            if events.len() >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        events.sort_by(|a, b| a.subscription_type.cmp(&b.subscription_type));
+        let totals = events
+            .iter()
+            .map(|event| {
+                (
+                    event.subscription_type.as_str(),
+                    event.subscription_id.as_str(),
+                    event.response_content_length,
+                    event.message_count,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            totals,
+            [
+                ("slot", "grpc-team-team", 200, 2),
+                ("slot_replay", "grpc-team-team", 50, 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use {
+    crate::billing::{BillingEvent, BillingSink, BillingSinkResult},
+    anyhow::Context,
+    log::info,
+    std::{
+        path::PathBuf,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    tokio::{
+        fs::{self, File, OpenOptions},
+        io::AsyncWriteExt,
+        sync::Mutex,
+    },
+};
+
+#[derive(Debug)]
+struct JsonlFile {
+    file: File,
+    size: u64,
+}
+
+/// Appends events as JSON lines, starting a new file once `rotate_size` is reached
+#[derive(Debug)]
+pub struct JsonlFileSink {
+    dir: PathBuf,
+    rotate_size: u64,
+    current: Mutex<Option<JsonlFile>>,
+}
+
+impl JsonlFileSink {
+    pub async fn new(dir: PathBuf, rotate_size: u64) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("failed to create billing dir {dir:?}"))?;
+
+        Ok(Self {
+            dir,
+            rotate_size,
+            current: Mutex::new(None),
+        })
+    }
+
+    async fn open_next(&self) -> anyhow::Result<JsonlFile> {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = self.dir.join(format!("billing-{ts}.jsonl"));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("failed to open billing file {path:?}"))?;
+        let size = file.metadata().await?.len();
+        info!("writing billing events to {path:?}");
+        Ok(JsonlFile { file, size })
+    }
+
+    async fn write(&self, buf: &[u8]) -> anyhow::Result<()> {
+        let mut current = self.current.lock().await;
+        if current
+            .as_ref()
+            .map(|current| current.size >= self.rotate_size)
+            .unwrap_or(true)
+        {
+            *current = Some(self.open_next().await?);
+        }
+
+        let current = current.as_mut().expect("opened");
+        current.file.write_all(buf).await?;
+        current.file.flush().await?;
+        current.size += buf.len() as u64;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl BillingSink for JsonlFileSink {
+    async fn send_batch(&self, events: Vec<BillingEvent>) -> BillingSinkResult {
+        let mut buf = Vec::with_capacity(events.len() * 256);
+        for event in events.iter() {
+            if let Err(error) = serde_json::to_writer(&mut buf, event) {
+                return Err((error.into(), events));
+            }
+            buf.push(b'\n');
+        }
+
+        match self.write(&buf).await {
+            Ok(()) => Ok(()),
+            Err(error) => Err((error, events)),
+        }
+    }
+}
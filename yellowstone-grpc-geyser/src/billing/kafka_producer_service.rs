@@ -0,0 +1,146 @@
+use {
+    crate::{
+        billing::{BillingEvent, BillingSink, BillingSinkResult},
+        config::{ConfigGrpc, ConfigKafkaSecurityProtocol},
+    },
+    anyhow::Context,
+    futures::future::join_all,
+    rdkafka::{
+        message::{Header, OwnedHeaders},
+        producer::{FutureProducer, FutureRecord},
+        ClientConfig,
+    },
+    serde::Serialize,
+    serde_json,
+    std::time::Duration,
+};
+
+#[derive(Debug, Serialize)]
+struct KafkaPayload<'a> {
+    namespace: &'static str,
+    records: [KafkaRecord<'a>; 1],
+}
+
+#[derive(Debug, Serialize)]
+struct KafkaRecord<'a> {
+    #[serde(rename = "partitionKey")]
+    partition_key: String,
+    data: &'a BillingEvent,
+}
+
+pub struct KafkaProducerService {
+    producer: FutureProducer,
+    kafka_topic: String,
+    kafka_queue_timeout: Duration,
+}
+
+impl KafkaProducerService {
+    pub fn new(config: &ConfigGrpc) -> anyhow::Result<Self> {
+        let kafka_topic = config
+            .billing_kafka_topic
+            .clone()
+            .context("billing_kafka_topic is required for kafka billing sink")?;
+        let producer = Self::build_producer(config)?;
+
+        Ok(Self {
+            producer,
+            kafka_topic,
+            kafka_queue_timeout: config.billing_kafka_send_queue_timeout,
+        })
+    }
+
+    async fn send_event(&self, event: BillingEvent) -> Result<(), (anyhow::Error, BillingEvent)> {
+        let kafka_payload = KafkaPayload {
+            namespace: "websocket-subscriptions",
+            records: [KafkaRecord {
+                partition_key: format!("team-{}", event.team_id),
+                data: &event,
+            }],
+        };
+
+        let payload = match serde_json::to_string(&kafka_payload) {
+            Ok(payload) => payload,
+            Err(e) => {
+                return Err((
+                    anyhow::anyhow!("Failed to serialize Kafka payload: {e:?}"),
+                    event,
+                ))
+            }
+        };
+        // key stays per team, the event id header lets consumers drop duplicates
+        let record = FutureRecord::to(&self.kafka_topic)
+            .payload(&payload)
+            .key(&kafka_payload.records[0].partition_key)
+            .headers(OwnedHeaders::new().insert(Header {
+                key: "event_id",
+                value: Some(&event.event_id),
+            }));
+
+        match self.producer.send(record, self.kafka_queue_timeout).await {
+            Ok(_) => Ok(()),
+            Err((e, _)) => Err((anyhow::anyhow!("Kafka delivery failed: {e:?}"), event)),
+        }
+    }
+
+    fn build_producer(config: &ConfigGrpc) -> anyhow::Result<FutureProducer> {
+        let brokers = config
+            .billing_kafka_brokers
+            .as_deref()
+            .context("billing_kafka_brokers is required for kafka billing sink")?;
+
+        let mut client_config = ClientConfig::new();
+        client_config
+            .set("bootstrap.servers", brokers)
+            .set("compression.type", "gzip")
+            .set("message.timeout.ms", "60000")
+            .set("batch.num.messages", "1000")
+            .set("linger.ms", "10");
+
+        let credentials = config
+            .billing_kafka_username
+            .as_deref()
+            .zip(config.billing_kafka_password.as_deref());
+        let security_protocol = config
+            .billing_kafka_security_protocol
+            .or_else(|| credentials.map(|_| ConfigKafkaSecurityProtocol::SaslSsl));
+        if let Some(security_protocol) = security_protocol {
+            client_config.set("security.protocol", security_protocol.as_str());
+        }
+        if let Some((user, pass)) = credentials {
+            client_config
+                .set(
+                    "sasl.mechanisms",
+                    config.billing_kafka_sasl_mechanism.as_str(),
+                )
+                .set("sasl.username", user)
+                .set("sasl.password", pass);
+        }
+
+        for (key, value) in &config.billing_kafka_properties {
+            client_config.set(key, value);
+        }
+
+        client_config
+            .create()
+            .context("failed to create Kafka producer")
+    }
+}
+
+#[tonic::async_trait]
+impl BillingSink for KafkaProducerService {
+    async fn send_batch(&self, events: Vec<BillingEvent>) -> BillingSinkResult {
+        let mut last_error = None;
+        let mut failed = vec![];
+        for result in join_all(events.into_iter().map(|event| self.send_event(event))).await {
+            if let Err((error, event)) = result {
+                last_error = Some(error);
+                failed.push(event);
+            }
+        }
+
+        match last_error {
+            Some(error) => Err((error, failed)),
+            None => Ok(()),
+        }
+    }
+}
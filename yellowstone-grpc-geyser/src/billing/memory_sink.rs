@@ -0,0 +1,28 @@
+use {
+    crate::billing::{BillingEvent, BillingSink, BillingSinkResult},
+    std::sync::Mutex,
+};
+
+/// Keeps delivered events in memory, intended for tests and local runs
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    events: Mutex<Vec<BillingEvent>>,
+}
+
+impl MemorySink {
+    pub fn events(&self) -> Vec<BillingEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    pub fn take_events(&self) -> Vec<BillingEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+#[tonic::async_trait]
+impl BillingSink for MemorySink {
+    async fn send_batch(&self, events: Vec<BillingEvent>) -> BillingSinkResult {
+        self.events.lock().unwrap().extend(events);
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+pub mod aggregator;
+pub mod client_billing;
+pub mod jsonl_file_sink;
+pub mod kafka_producer_service;
+pub mod memory_sink;
+pub mod spool;
+
+use {
+    crate::{
+        billing::{
+            aggregator::BillingAggregator,
+            jsonl_file_sink::JsonlFileSink,
+            kafka_producer_service::KafkaProducerService,
+            memory_sink::MemorySink,
+            spool::{BillingSpool, SpoolSender},
+        },
+        config::{ConfigBillingSink, ConfigGrpc},
+        metrics::{BILLING_EVENTS_SENT, BILLING_EVENT_SEND_DURATION, BILLING_EVENT_SEND_ERRORS},
+    },
+    anyhow::Context,
+    log::error,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        sync::Arc,
+        time::{Instant, SystemTime, UNIX_EPOCH},
+    },
+    tokio::{
+        sync::mpsc::{self, error::TrySendError, Receiver, Sender},
+        task::JoinHandle,
+        time::{interval, Duration, MissedTickBehavior},
+    },
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BillingEvent {
+    pub team_id: String,
+    pub app_id: String,
+    pub eth_method: String,
+    pub eth_network: String,
+    pub subscription_id: String,
+    pub subscription_type: String,
+    pub log_source: String,
+    pub response_content_length: u64,
+    #[serde(default)]
+    pub message_count: u64,
+    /// `response_content_length` multiplied by the configured weight of the message type
+    #[serde(default)]
+    pub weighted_content_length: u64,
+    /// Idempotency key: `{instance}:{client}:{sequence}:{subscription_type}`
+    #[serde(default)]
+    pub event_id: String,
+    /// Billing tick sequence number of the client
+    #[serde(default)]
+    pub sequence: u64,
+    /// Covered time window `[window_start_ms, window_end_ms)` in unix milliseconds
+    #[serde(default)]
+    pub window_start_ms: u64,
+    #[serde(default)]
+    pub window_end_ms: u64,
+}
+
+/// Price multipliers by message type, checked by `subscription_type` first
+/// (e.g. `account_replay`) and then by `eth_method` (e.g. `account`), 1 by default
+#[derive(Debug, Default, Clone)]
+pub struct BillingWeights {
+    weights: HashMap<String, f64>,
+}
+
+impl BillingWeights {
+    pub const fn new(weights: HashMap<String, f64>) -> Self {
+        Self { weights }
+    }
+
+    pub fn apply(&self, event: &mut BillingEvent) {
+        let weight = self
+            .weights
+            .get(&event.subscription_type)
+            .or_else(|| self.weights.get(&event.eth_method))
+            .copied()
+            .unwrap_or(1.0)
+            .max(0.0);
+        event.weighted_content_length =
+            (event.response_content_length as f64 * weight).round() as u64;
+    }
+}
+
+/// Identifies the plugin run in billing event ids, so ids stay unique across restarts
+pub fn billing_instance_id(config: &ConfigGrpc) -> String {
+    let name = config.billing_instance_id.clone().unwrap_or_else(|| {
+        hostname::get()
+            .ok()
+            .and_then(|name| name.into_string().ok())
+            .unwrap_or_else(|| "unknown".to_owned())
+    });
+    format!("{name}-{}", unix_ms(SystemTime::now()))
+}
+
+pub fn unix_ms(ts: SystemTime) -> u64 {
+    ts.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// On failure the sink returns the error and the events which were not delivered
+pub type BillingSinkResult = Result<(), (anyhow::Error, Vec<BillingEvent>)>;
+
+#[tonic::async_trait]
+pub trait BillingSink: Send + Sync + 'static {
+    async fn send_batch(&self, events: Vec<BillingEvent>) -> BillingSinkResult;
+}
+
+pub async fn create_billing_sink(config: &ConfigGrpc) -> anyhow::Result<Arc<dyn BillingSink>> {
+    Ok(match config.billing_sink {
+        ConfigBillingSink::Kafka => Arc::new(KafkaProducerService::new(config)?),
+        ConfigBillingSink::Jsonl => Arc::new(
+            JsonlFileSink::new(
+                config
+                    .billing_file_dir
+                    .clone()
+                    .context("billing_file_dir is required for jsonl billing sink")?,
+                config.billing_file_rotate_size,
+            )
+            .await?,
+        ),
+        ConfigBillingSink::Memory => Arc::new(MemorySink::default()),
+    })
+}
+
+pub fn create_billing_spool(config: &ConfigGrpc) -> anyhow::Result<Option<Arc<BillingSpool>>> {
+    config
+        .billing_spool_dir
+        .clone()
+        .map(|dir| {
+            BillingSpool::new(dir, config.billing_spool_segment_size)
+                .context("failed to open billing spool")
+                .map(Arc::new)
+        })
+        .transpose()
+}
+
+/// Sending side of the billing channel, events which don't fit into the channel
+/// are written to the spool (if configured) instead of being dropped.
+/// Weights are applied here, so spooled events are billed the same as delivered ones
+#[derive(Debug, Clone)]
+pub struct BillingSender {
+    tx: Sender<BillingEvent>,
+    spool: Option<SpoolSender>,
+    weights: Arc<BillingWeights>,
+}
+
+impl BillingSender {
+    pub fn try_send(&self, mut event: BillingEvent) -> anyhow::Result<()> {
+        self.weights.apply(&mut event);
+        match (self.tx.try_send(event), &self.spool) {
+            (Ok(()), _) => Ok(()),
+            (Err(TrySendError::Full(event)), Some(spool)) => spool.send(vec![event]),
+            (Err(error), _) => Err(anyhow::anyhow!("{error}")),
+        }
+    }
+}
+
+/// Collects events from client loops and delivers them to the sink in batches
+#[derive(Debug)]
+pub struct BillingService {
+    pub sender: BillingSender,
+}
+
+impl BillingService {
+    /// Events are summed per team over `aggregation_window`, `None` forwards
+    /// per-connection events as they are
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        sink: Arc<dyn BillingSink>,
+        spool: Option<Arc<BillingSpool>>,
+        instance_id: Arc<str>,
+        send_channel_size: usize,
+        send_batch_size: usize,
+        spool_replay_interval: Duration,
+        aggregation_window: Option<Duration>,
+        weights: BillingWeights,
+    ) -> (Self, JoinHandle<()>) {
+        let send_batch_size = send_batch_size.max(1);
+        let spool = spool.map(|spool| {
+            tokio::spawn(Arc::clone(&spool).run_replay(
+                Arc::clone(&sink),
+                spool_replay_interval,
+                send_batch_size,
+            ));
+            spool.spawn_writer().0
+        });
+
+        let (tx, rx) = mpsc::channel(send_channel_size);
+        let handle = tokio::spawn(Self::run(
+            sink,
+            spool.clone(),
+            rx,
+            send_batch_size,
+            aggregation_window.map(|window| (BillingAggregator::new(instance_id), window)),
+        ));
+        (
+            Self {
+                sender: BillingSender {
+                    tx,
+                    spool,
+                    weights: Arc::new(weights),
+                },
+            },
+            handle,
+        )
+    }
+
+    async fn run(
+        sink: Arc<dyn BillingSink>,
+        spool: Option<SpoolSender>,
+        mut rx: Receiver<BillingEvent>,
+        send_batch_size: usize,
+        mut aggregation: Option<(BillingAggregator, Duration)>,
+    ) {
+        // ticker is not polled without aggregation, period doesn't matter then
+        let mut aggregation_ticker = interval(
+            aggregation
+                .as_ref()
+                .map(|(_, window)| *window)
+                .unwrap_or(Duration::from_secs(60)),
+        );
+        aggregation_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        aggregation_ticker.reset();
+
+        let mut events = Vec::with_capacity(send_batch_size);
+        loop {
+            tokio::select! {
+                count = rx.recv_many(&mut events, send_batch_size) => {
+                    if count == 0 {
+                        break;
+                    }
+                    if let Some((aggregator, _)) = &mut aggregation {
+                        for event in events.drain(..) {
+                            aggregator.add(event);
+                        }
+                    } else {
+                        Self::deliver(&sink, &spool, std::mem::take(&mut events)).await;
+                    }
+                }
+                _ = aggregation_ticker.tick(), if aggregation.is_some() => {
+                    if let Some((aggregator, _)) = &mut aggregation {
+                        Self::deliver_batched(&sink, &spool, aggregator.drain(), send_batch_size).await;
+                    }
+                }
+            }
+        }
+
+        // channel is closed, send what was aggregated in the last window
+        if let Some((aggregator, _)) = &mut aggregation {
+            Self::deliver_batched(&sink, &spool, aggregator.drain(), send_batch_size).await;
+        }
+    }
+
+    async fn deliver_batched(
+        sink: &Arc<dyn BillingSink>,
+        spool: &Option<SpoolSender>,
+        mut events: Vec<BillingEvent>,
+        send_batch_size: usize,
+    ) {
+        while !events.is_empty() {
+            let rest = events.split_off(events.len().min(send_batch_size));
+            Self::deliver(sink, spool, events).await;
+            events = rest;
+        }
+    }
+
+    async fn deliver(
+        sink: &Arc<dyn BillingSink>,
+        spool: &Option<SpoolSender>,
+        events: Vec<BillingEvent>,
+    ) {
+        let start = Instant::now();
+        let total = events.len() as u64;
+
+        match sink.send_batch(events).await {
+            Ok(()) => {
+                BILLING_EVENTS_SENT.inc_by(total);
+                BILLING_EVENT_SEND_DURATION.observe(start.elapsed().as_secs_f64());
+            }
+            Err((error, failed)) => {
+                let failed_count = failed.len() as u64;
+                BILLING_EVENTS_SENT.inc_by(total.saturating_sub(failed_count));
+                BILLING_EVENT_SEND_ERRORS.inc_by(failed_count);
+                error!("failed to deliver {failed_count} of {total} billing events: {error:?}");
+
+                if let Some(spool) = spool {
+                    if let Err(error) = spool.send(failed) {
+                        error!("failed to spool {failed_count} billing events: {error:?}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(eth_method: &str, sequence: u64, size: u64) -> BillingEvent {
+        BillingEvent {
+            team_id: "team".to_owned(),
+            app_id: "app".to_owned(),
+            eth_method: eth_method.to_owned(),
+            eth_network: "SOLANA_MAINNET".to_owned(),
+            subscription_id: "grpc-client-1".to_owned(),
+            subscription_type: eth_method.to_owned(),
+            log_source: "grpc".to_owned(),
+            response_content_length: size,
+            message_count: 1,
+            weighted_content_length: size,
+            event_id: format!("test:1:{sequence}:{eth_method}"),
+            sequence,
+            window_start_ms: 0,
+            window_end_ms: 0,
+        }
+    }
+
+    async fn wait_events(sink: &MemorySink, count: usize) -> Vec<BillingEvent> {
+        for _ in 0..500 {
+            let events = sink.events();
+            if events.len() >= count {
+                return events;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("expected {count} billing events, got {:?}", sink.events());
+    }
+
+    #[tokio::test]
+    async fn test_overflow_is_weighted() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = Arc::new(MemorySink::default());
+        let spool = Arc::new(BillingSpool::new(dir.path().to_owned(), 1 << 20).unwrap());
+        let (service, _task) = BillingService::new(
+            Arc::clone(&sink) as Arc<dyn BillingSink>,
+            Some(spool),
+            "test".into(),
+            1,
+            16,
+            Duration::from_millis(10),
+            None,
+            BillingWeights::new(HashMap::from([("account".to_owned(), 2.5)])),
+        );
+
+        // the service task doesn't run before the test yields, so only the first
+        // event fits into the channel and the rest goes to the spool
+        for sequence in 0..3 {
+            service
+                .sender
+                .try_send(event("account", sequence, 100))
+                .unwrap();
+        }
+        service.sender.try_send(event("slot", 3, 100)).unwrap();
+
+        let mut events = wait_events(&sink, 4).await;
+        events.sort_by_key(|event| event.sequence);
+        let weighted = events
+            .iter()
+            .map(|event| event.weighted_content_length)
+            .collect::<Vec<_>>();
+        assert_eq!(weighted, [250, 250, 250, 100]);
+        assert!(events
+            .iter()
+            .all(|event| event.response_content_length == 100));
+    }
+}
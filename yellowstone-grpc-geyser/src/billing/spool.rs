@@ -0,0 +1,538 @@
+use {
+    crate::{
+        billing::{BillingEvent, BillingSink},
+        metrics::{
+            BILLING_EVENTS_SENT, BILLING_EVENTS_SPOOLED, BILLING_SPOOL_FAILED_SEGMENTS,
+            BILLING_SPOOL_INVALID_EVENTS, BILLING_SPOOL_OLDEST_PENDING_AGE,
+            BILLING_SPOOL_SIZE_BYTES,
+        },
+    },
+    anyhow::Context,
+    log::{error, info, warn},
+    std::{
+        collections::VecDeque,
+        fs::{self, File, OpenOptions},
+        io::{BufWriter, Write},
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+    tokio::{
+        sync::mpsc,
+        task::{spawn_blocking, JoinHandle},
+        time::interval,
+    },
+};
+
+#[derive(Debug)]
+struct SpoolSegment {
+    /// Creation time in unix milliseconds, also used as file name
+    id: u64,
+    size: u64,
+}
+
+#[derive(Debug)]
+struct SpoolWriter {
+    id: u64,
+    file: BufWriter<File>,
+}
+
+#[derive(Debug, Default)]
+struct SpoolInner {
+    segments: VecDeque<SpoolSegment>,
+    writer: Option<SpoolWriter>,
+}
+
+/// Outcome of a replay, counted per segment
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpoolReplayStats {
+    /// Segments delivered completely and removed
+    pub segments: usize,
+    pub events: usize,
+    /// Lines which are not a valid event, e.g. truncated by a crash during a write
+    pub invalid_events: usize,
+    /// Unreadable segments moved aside as `.failed`
+    pub failed_segments: usize,
+}
+
+/// Write-ahead spool for billing events which could not be delivered,
+/// events are stored as JSON lines in segment files and replayed in order.
+///
+/// File operations block, async code writes through `SpoolSender`
+/// and replays on the blocking pool.
+#[derive(Debug)]
+pub struct BillingSpool {
+    dir: PathBuf,
+    segment_size: u64,
+    inner: Mutex<SpoolInner>,
+}
+
+impl BillingSpool {
+    pub fn new(dir: PathBuf, segment_size: u64) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create spool dir {dir:?}"))?;
+
+        let mut segments = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            // leftover of a rewrite interrupted by a crash, the segment itself is intact
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            let Some(id) = Self::parse_segment_name(&path) else {
+                continue;
+            };
+            let size = fs::metadata(&path)?.len();
+            if size == 0 {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            segments.push(SpoolSegment { id, size });
+        }
+        segments.sort_by_key(|segment| segment.id);
+        if !segments.is_empty() {
+            info!(
+                "billing spool {dir:?} has {} pending segments",
+                segments.len()
+            );
+        }
+
+        let spool = Self {
+            dir,
+            segment_size,
+            inner: Mutex::new(SpoolInner {
+                segments: segments.into(),
+                writer: None,
+            }),
+        };
+        spool.update_metrics(&spool.inner.lock().unwrap());
+        Ok(spool)
+    }
+
+    fn parse_segment_name(path: &Path) -> Option<u64> {
+        if path.extension()? != "jsonl" {
+            return None;
+        }
+        path.file_stem()?.to_str()?.parse().ok()
+    }
+
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{id:020}.jsonl"))
+    }
+
+    fn now_ms() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+
+    fn update_metrics(&self, inner: &SpoolInner) {
+        let size: u64 = inner.segments.iter().map(|segment| segment.size).sum();
+        BILLING_SPOOL_SIZE_BYTES.set(size as i64);
+
+        let age = inner
+            .segments
+            .front()
+            .map(|segment| Self::now_ms().saturating_sub(segment.id) / 1_000)
+            .unwrap_or(0);
+        BILLING_SPOOL_OLDEST_PENDING_AGE.set(age as i64);
+    }
+
+    fn encode(events: &[BillingEvent]) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(events.len() * 256);
+        for event in events {
+            serde_json::to_writer(&mut buf, event)?;
+            buf.push(b'\n');
+        }
+        Ok(buf)
+    }
+
+    /// Appends events to the newest segment, starting a new one once `segment_size` is reached
+    pub fn append(&self, events: &[BillingEvent]) -> anyhow::Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let buf = Self::encode(events)?;
+
+        BILLING_EVENTS_SPOOLED.inc_by(events.len() as u64);
+        let mut inner = self.inner.lock().unwrap();
+        let need_new_segment = match (&inner.writer, inner.segments.back()) {
+            (Some(writer), Some(segment)) => {
+                writer.id != segment.id || segment.size >= self.segment_size
+            }
+            _ => true,
+        };
+        if need_new_segment {
+            if let Some(mut writer) = inner.writer.take() {
+                writer.file.flush()?;
+                writer.file.get_ref().sync_data()?;
+            }
+
+            let id = Self::now_ms().max(inner.segments.back().map_or(0, |s| s.id + 1));
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.segment_path(id))?;
+            inner.writer = Some(SpoolWriter {
+                id,
+                file: BufWriter::new(file),
+            });
+            inner.segments.push_back(SpoolSegment { id, size: 0 });
+        }
+
+        let writer = inner.writer.as_mut().expect("opened");
+        writer.file.write_all(&buf)?;
+        writer.file.flush()?;
+        inner.segments.back_mut().expect("created").size += buf.len() as u64;
+
+        self.update_metrics(&inner);
+        Ok(())
+    }
+
+    /// Starts the task which appends events sent through the returned sender,
+    /// the task ends once all senders are dropped
+    pub fn spawn_writer(self: &Arc<Self>) -> (SpoolSender, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<BillingEvent>>();
+        let spool = Arc::clone(self);
+        let handle = tokio::spawn(async move {
+            let mut batches = Vec::new();
+            while rx.recv_many(&mut batches, 64).await > 0 {
+                let events = batches.drain(..).flatten().collect::<Vec<_>>();
+                let count = events.len();
+                let spool = Arc::clone(&spool);
+                match spawn_blocking(move || spool.append(&events)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(error)) => error!("failed to spool {count} billing events: {error:?}"),
+                    Err(error) => error!("failed to spool {count} billing events: {error}"),
+                }
+            }
+        });
+        (SpoolSender { tx }, handle)
+    }
+
+    /// Returns the oldest segment, sealing it first if it's still open for writes
+    fn take_oldest(&self) -> anyhow::Result<Option<u64>> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(id) = inner.segments.front().map(|segment| segment.id) else {
+            return Ok(None);
+        };
+        if inner.writer.as_ref().map(|writer| writer.id) == Some(id) {
+            let mut writer = inner.writer.take().expect("checked");
+            writer.file.flush()?;
+            writer.file.get_ref().sync_data()?;
+        }
+        Ok(Some(id))
+    }
+
+    /// Removes a delivered segment, or replaces it with the events still pending,
+    /// the new content is written to a temporary file first, so a crash keeps
+    /// either the old or the new segment
+    fn complete(&self, id: u64, pending: &[BillingEvent]) -> anyhow::Result<()> {
+        let path = self.segment_path(id);
+        let mut inner = self.inner.lock().unwrap();
+        if pending.is_empty() {
+            fs::remove_file(&path)?;
+            inner.segments.retain(|segment| segment.id != id);
+        } else {
+            let buf = Self::encode(pending)?;
+            let tmp_path = path.with_extension("jsonl.tmp");
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(&buf)?;
+            file.sync_data()?;
+            fs::rename(&tmp_path, &path)?;
+            if let Some(segment) = inner.segments.iter_mut().find(|segment| segment.id == id) {
+                segment.size = buf.len() as u64;
+            }
+        }
+        self.update_metrics(&inner);
+        Ok(())
+    }
+
+    /// Moves an unreadable segment aside, so it doesn't block the segments after it
+    fn quarantine(&self, id: u64) -> anyhow::Result<()> {
+        let path = self.segment_path(id);
+        let mut inner = self.inner.lock().unwrap();
+        inner.segments.retain(|segment| segment.id != id);
+        self.update_metrics(&inner);
+        fs::rename(&path, path.with_extension("jsonl.failed"))?;
+        Ok(())
+    }
+
+    async fn blocking<T, F>(self: &Arc<Self>, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Self) -> anyhow::Result<T> + Send + 'static,
+    {
+        let spool = Arc::clone(self);
+        spawn_blocking(move || f(&spool)).await?
+    }
+
+    /// Replays segments oldest first, stops on the first delivery failure,
+    /// unreadable segments are skipped
+    pub async fn replay(
+        self: &Arc<Self>,
+        sink: &dyn BillingSink,
+        batch_size: usize,
+    ) -> anyhow::Result<SpoolReplayStats> {
+        let mut stats = SpoolReplayStats::default();
+        while let Some(id) = self.blocking(Self::take_oldest).await? {
+            let path = self.segment_path(id);
+            let data = match tokio::fs::read(&path).await {
+                Ok(data) => data,
+                Err(error) => {
+                    error!("failed to read spool segment {path:?}, moving it aside: {error}");
+                    BILLING_SPOOL_FAILED_SEGMENTS.inc();
+                    stats.failed_segments += 1;
+                    self.blocking(move |spool| spool.quarantine(id)).await?;
+                    continue;
+                }
+            };
+
+            let mut events = Vec::new();
+            let mut invalid_events = 0;
+            for line in data.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+                match serde_json::from_slice::<BillingEvent>(line) {
+                    Ok(event) => events.push(event),
+                    Err(_) => invalid_events += 1,
+                }
+            }
+            if invalid_events > 0 {
+                error!("skip {invalid_events} invalid events in spool segment {path:?}");
+                BILLING_SPOOL_INVALID_EVENTS.inc_by(invalid_events as u64);
+                stats.invalid_events += invalid_events;
+            }
+
+            while !events.is_empty() {
+                let rest = events.split_off(events.len().min(batch_size));
+                let total = events.len();
+                if let Err((error, mut failed)) = sink.send_batch(events).await {
+                    BILLING_EVENTS_SENT.inc_by((total - failed.len()) as u64);
+                    stats.events += total - failed.len();
+                    warn!(
+                        "failed to replay {} of {total} spooled billing events: {error:?}",
+                        failed.len()
+                    );
+                    failed.extend(rest);
+                    self.blocking(move |spool| spool.complete(id, &failed))
+                        .await?;
+                    return Ok(stats);
+                }
+                BILLING_EVENTS_SENT.inc_by(total as u64);
+                stats.events += total;
+                events = rest;
+            }
+
+            self.blocking(move |spool| spool.complete(id, &[])).await?;
+            stats.segments += 1;
+            info!("replayed billing spool segment {path:?}");
+        }
+        Ok(stats)
+    }
+
+    pub async fn run_replay(
+        self: Arc<Self>,
+        sink: Arc<dyn BillingSink>,
+        replay_interval: Duration,
+        batch_size: usize,
+    ) {
+        let mut ticker = interval(replay_interval);
+        loop {
+            ticker.tick().await;
+            if let Err(error) = self.replay(sink.as_ref(), batch_size).await {
+                error!("failed to replay billing spool: {error:?}");
+            }
+            self.update_metrics(&self.inner.lock().unwrap());
+        }
+    }
+}
+
+/// Sends events to the spool writer task without blocking the caller
+#[derive(Debug, Clone)]
+pub struct SpoolSender {
+    tx: mpsc::UnboundedSender<Vec<BillingEvent>>,
+}
+
+impl SpoolSender {
+    pub fn send(&self, events: Vec<BillingEvent>) -> anyhow::Result<()> {
+        self.tx
+            .send(events)
+            .map_err(|_| anyhow::anyhow!("billing spool writer is stopped"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::billing::{memory_sink::MemorySink, BillingSinkResult},
+    };
+
+    fn event(sequence: u64) -> BillingEvent {
+        BillingEvent {
+            team_id: "team".to_owned(),
+            app_id: "app".to_owned(),
+            eth_method: "account".to_owned(),
+            eth_network: "SOLANA_MAINNET".to_owned(),
+            subscription_id: "1".to_owned(),
+            subscription_type: "account".to_owned(),
+            log_source: "geyser".to_owned(),
+            response_content_length: 100,
+            message_count: 1,
+            weighted_content_length: 100,
+            event_id: format!("test:1:{sequence}:account"),
+            sequence,
+            window_start_ms: 0,
+            window_end_ms: 0,
+        }
+    }
+
+    fn sequences(events: &[BillingEvent]) -> Vec<u64> {
+        events.iter().map(|event| event.sequence).collect()
+    }
+
+    /// Delivers the first `accept` events of every batch
+    struct PartialSink {
+        accept: usize,
+        sink: MemorySink,
+    }
+
+    #[tonic::async_trait]
+    impl BillingSink for PartialSink {
+        async fn send_batch(&self, mut events: Vec<BillingEvent>) -> BillingSinkResult {
+            let failed = events.split_off(events.len().min(self.accept));
+            self.sink.send_batch(events).await?;
+            if failed.is_empty() {
+                Ok(())
+            } else {
+                Err((anyhow::anyhow!("sink is down"), failed))
+            }
+        }
+    }
+
+    fn segment_files(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_append_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        // every append starts a new segment
+        let spool = Arc::new(BillingSpool::new(dir.path().to_owned(), 1).unwrap());
+        spool.append(&[event(1), event(2)]).unwrap();
+        spool.append(&[event(3)]).unwrap();
+        assert_eq!(segment_files(dir.path()).len(), 2);
+
+        let sink = MemorySink::default();
+        let stats = spool.replay(&sink, 2).await.unwrap();
+        assert_eq!(sequences(&sink.events()), [1, 2, 3]);
+        assert_eq!(
+            stats,
+            SpoolReplayStats {
+                segments: 2,
+                events: 3,
+                ..Default::default()
+            }
+        );
+        assert!(segment_files(dir.path()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replay_keeps_undelivered_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = Arc::new(BillingSpool::new(dir.path().to_owned(), 1 << 20).unwrap());
+        spool.append(&[event(1), event(2), event(3)]).unwrap();
+
+        let sink = PartialSink {
+            accept: 1,
+            sink: MemorySink::default(),
+        };
+        let stats = spool.replay(&sink, 3).await.unwrap();
+        assert_eq!(sequences(&sink.sink.events()), [1]);
+        assert_eq!(stats.events, 1);
+        assert_eq!(stats.segments, 0);
+        // rewritten through a temporary file, which is gone after the rename
+        assert_eq!(segment_files(dir.path()).len(), 1);
+        assert!(!segment_files(dir.path())[0].ends_with(".tmp"));
+
+        // a restart replays the rest
+        drop(spool);
+        let spool = Arc::new(BillingSpool::new(dir.path().to_owned(), 1 << 20).unwrap());
+        let sink = MemorySink::default();
+        spool.replay(&sink, 3).await.unwrap();
+        assert_eq!(sequences(&sink.events()), [2, 3]);
+        assert!(segment_files(dir.path()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replay_skips_truncated_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut data = BillingSpool::encode(&[event(1), event(2)]).unwrap();
+        let line = BillingSpool::encode(&[event(3)]).unwrap();
+        data.extend_from_slice(&line[..line.len() / 2]);
+        fs::write(dir.path().join(format!("{:020}.jsonl", 1)), data).unwrap();
+        // an interrupted rewrite
+        fs::write(dir.path().join(format!("{:020}.jsonl.tmp", 1)), b"{").unwrap();
+
+        let spool = Arc::new(BillingSpool::new(dir.path().to_owned(), 1 << 20).unwrap());
+        assert_eq!(segment_files(dir.path()).len(), 1);
+        // appended after the truncated line in a new segment
+        spool.append(&[event(4)]).unwrap();
+
+        let sink = MemorySink::default();
+        let stats = spool.replay(&sink, 10).await.unwrap();
+        assert_eq!(sequences(&sink.events()), [1, 2, 4]);
+        assert_eq!(
+            stats,
+            SpoolReplayStats {
+                segments: 2,
+                events: 3,
+                invalid_events: 1,
+                failed_segments: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_moves_unreadable_segment_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        // a directory can't be read as a segment
+        fs::create_dir(dir.path().join(format!("{:020}.jsonl", 1))).unwrap();
+        fs::write(
+            dir.path().join(format!("{:020}.jsonl", 2)),
+            BillingSpool::encode(&[event(1)]).unwrap(),
+        )
+        .unwrap();
+
+        let spool = Arc::new(BillingSpool::new(dir.path().to_owned(), 1 << 20).unwrap());
+        let sink = MemorySink::default();
+        let stats = spool.replay(&sink, 10).await.unwrap();
+        assert_eq!(sequences(&sink.events()), [1]);
+        assert_eq!(stats.segments, 1);
+        assert_eq!(stats.failed_segments, 1);
+        assert_eq!(
+            segment_files(dir.path()),
+            [format!("{:020}.jsonl.failed", 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = Arc::new(BillingSpool::new(dir.path().to_owned(), 1 << 20).unwrap());
+        let (sender, writer) = spool.spawn_writer();
+        sender.send(vec![event(1)]).unwrap();
+        sender.send(vec![event(2), event(3)]).unwrap();
+        drop(sender);
+        writer.await.unwrap();
+
+        let sink = MemorySink::default();
+        spool.replay(&sink, 10).await.unwrap();
+        assert_eq!(sequences(&sink.events()), [1, 2, 3]);
+    }
+}
@@ -4,7 +4,12 @@ use {
     },
     serde::{de, Deserialize, Deserializer},
     std::{
-        collections::HashSet, fmt, fs::read_to_string, net::SocketAddr, path::Path, str::FromStr,
+        collections::{HashMap, HashSet},
+        fmt,
+        fs::read_to_string,
+        net::SocketAddr,
+        path::{Path, PathBuf},
+        str::FromStr,
         time::Duration,
     },
     tokio::sync::Semaphore,
@@ -26,13 +31,26 @@ pub struct Config {
     /// Collect client filters, processed slot and make it available on prometheus port `/debug_clients`
     #[serde(default)]
     pub debug_clients_http: bool,
+    /// How often the config file is checked for changes, reloadable keys are applied
+    /// without a plugin reload, not checked if unset
+    #[serde(default, with = "humantime_serde")]
+    pub config_reload_interval: Option<Duration>,
 }
 
 impl Config {
-    fn load_from_str(config: &str) -> PluginResult<Self> {
-        serde_json::from_str(config).map_err(|error| GeyserPluginError::ConfigFileReadError {
-            msg: error.to_string(),
-        })
+    pub fn load_from_str(config: &str) -> PluginResult<Self> {
+        let config: Self = serde_json::from_str(config).map_err(|error| {
+            GeyserPluginError::ConfigFileReadError {
+                msg: error.to_string(),
+            }
+        })?;
+        config
+            .grpc
+            .validate()
+            .map_err(|error| GeyserPluginError::ConfigFileReadError {
+                msg: error.to_string(),
+            })?;
+        Ok(config)
     }
 
     pub fn load_from_file<P: AsRef<Path>>(file: P) -> PluginResult<Self> {
@@ -180,6 +198,17 @@ pub struct ConfigGrpc {
     pub filter_limits: FilterLimits,
     /// x_token to enforce on connections
     pub x_token: Option<String>,
+    /// Identity of the clients opening streams
+    #[serde(default)]
+    pub auth: ConfigAuth,
+    /// Network served by this instance, checked against the genesis hash on startup
+    pub network: ConfigNetwork,
+    /// `GeyserAdmin` service to list and disconnect clients, served on its own address
+    #[serde(default)]
+    pub admin: Option<ConfigGrpcAdmin>,
+    /// When the health service and `/ready` report the node as not serving
+    #[serde(default)]
+    pub health: ConfigHealth,
     /// Filter name size limit
     #[serde(default = "ConfigGrpc::default_filter_name_size_limit")]
     pub filter_name_size_limit: usize,
@@ -208,32 +237,112 @@ pub struct ConfigGrpc {
     pub server_initial_connection_window_size: Option<u32>,
     #[serde(default)]
     pub server_initial_stream_window_size: Option<u32>,
-    pub billing_kafka_topic: String,
-    pub billing_kafka_brokers: String,
+    /// Instance name used in billing event ids, hostname by default
+    #[serde(default)]
+    pub billing_instance_id: Option<String>,
+    /// Backend used to deliver billing events
+    #[serde(default)]
+    pub billing_sink: ConfigBillingSink,
+    #[serde(default)]
+    pub billing_kafka_topic: Option<String>,
+    #[serde(default)]
+    pub billing_kafka_brokers: Option<String>,
     #[serde(default)]
     pub billing_kafka_username: Option<String>,
     #[serde(default)]
     pub billing_kafka_password: Option<String>,
+    /// `SASL_SSL` if credentials are set, librdkafka default otherwise
+    #[serde(default)]
+    pub billing_kafka_security_protocol: Option<ConfigKafkaSecurityProtocol>,
+    #[serde(default)]
+    pub billing_kafka_sasl_mechanism: ConfigKafkaSaslMechanism,
+    /// Extra librdkafka properties, applied last so they override the defaults
+    #[serde(default)]
+    pub billing_kafka_properties: HashMap<String, String>,
     #[serde(
         default = "ConfigGrpc::default_billing_kafka_send_queue_timeout",
         with = "humantime_serde"
     )]
     pub billing_kafka_send_queue_timeout: Duration,
+    /// Capacity of the channel between client loops and the billing sink
+    #[serde(default = "ConfigGrpc::default_billing_kafka_send_channel_size")]
+    pub billing_kafka_send_channel_size: usize,
+    /// Max number of billing events handed to the sink at once
+    #[serde(
+        default = "ConfigGrpc::default_billing_send_batch_size",
+        deserialize_with = "deserialize_int_str"
+    )]
+    pub billing_send_batch_size: usize,
+    /// Directory for `jsonl` billing sink files
+    #[serde(default)]
+    pub billing_file_dir: Option<PathBuf>,
+    /// `jsonl` billing sink starts a new file once the current one reaches this size
     #[serde(
-        default = "ConfigGrpc::default_billing_kafka_send_channel_size",
+        default = "ConfigGrpc::default_billing_file_rotate_size",
+        deserialize_with = "deserialize_int_str"
     )]
-    pub billing_kafka_send_channel_size: usize,    #[serde(
+    pub billing_file_rotate_size: u64,
+    /// Billing events of all connections are summed per team, app, network
+    /// and message type over this window before they are sent
+    #[serde(
+        default = "ConfigGrpc::default_billing_aggregation_window",
+        with = "humantime_serde"
+    )]
+    pub billing_aggregation_window: Duration,
+    /// Send an event per connection and billing tick instead of team aggregates
+    #[serde(default)]
+    pub billing_per_connection_events: bool,
+    /// Price multiplier by message type (`account`, `block`, `account_replay`, ...),
+    /// applied to `weighted_content_length` of billing events, 1 by default
+    #[serde(default)]
+    pub billing_weights: HashMap<String, f64>,
+    /// Directory for billing events which failed to be delivered or didn't fit into the channel
+    #[serde(default)]
+    pub billing_spool_dir: Option<PathBuf>,
+    /// Billing spool starts a new segment once the current one reaches this size
+    #[serde(
+        default = "ConfigGrpc::default_billing_spool_segment_size",
+        deserialize_with = "deserialize_int_str"
+    )]
+    pub billing_spool_segment_size: u64,
+    /// Interval between attempts to deliver spooled billing events
+    #[serde(
+        default = "ConfigGrpc::default_billing_spool_replay_interval",
+        with = "humantime_serde"
+    )]
+    pub billing_spool_replay_interval: Duration,
+    #[serde(
         default = "ConfigGrpc::default_billing_ticker_interval",
         with = "humantime_serde"
     )]
     pub billing_ticker_interval: Duration,
-    pub redis_url: String,
+    /// Where team caps are read from
+    #[serde(default)]
+    pub quota_backend: ConfigQuotaBackend,
+    /// JSON or TOML file for the `file` quota backend
+    #[serde(default)]
+    pub quota_file_path: Option<PathBuf>,
+    #[serde(default)]
+    pub redis_url: Option<String>,
+    /// Resolve the master through Sentinel instead of `redis_url`
+    #[serde(default)]
+    pub redis_sentinel: Option<ConfigRedisSentinel>,
+    /// Cluster seed nodes, used instead of `redis_url`
+    #[serde(default)]
+    pub redis_cluster_urls: Vec<String>,
+    #[serde(default)]
     pub redis_prefix: String,
     #[serde(
         default = "ConfigGrpc::default_redis_cache_ttl",
         with = "humantime_serde"
     )]
     pub redis_cache_ttl: Duration,
+    /// Cache lifetime of keys missing in Redis
+    #[serde(
+        default = "ConfigGrpc::default_redis_cache_negative_ttl",
+        with = "humantime_serde"
+    )]
+    pub redis_cache_negative_ttl: Duration,
     #[serde(
         default = "ConfigGrpc::default_redis_cache_capacity",
         deserialize_with = "deserialize_int_str"
@@ -245,6 +354,21 @@ pub struct ConfigGrpc {
         with = "humantime_serde"
     )]
     pub redis_background_buffer: Duration,
+    /// While Redis is failing the last known value is served up to `redis_cache_ttl` +
+    /// `redis_cache_max_stale` after it was fetched, later lookups fail
+    #[serde(
+        default = "ConfigGrpc::default_redis_cache_max_stale",
+        with = "humantime_serde"
+    )]
+    pub redis_cache_max_stale: Duration,
+    /// Apply cap changes pushed by Redis right away, polling every `quota_check_interval`
+    /// is kept to reconcile missed events and can be made less frequent,
+    /// only supported with `redis_url`
+    #[serde(default)]
+    pub redis_push_mode: ConfigRedisPushMode,
+    /// Pub/sub channel for the `pubsub` push mode, `{redis_prefix}:events` by default
+    #[serde(default)]
+    pub redis_push_channel: Option<String>,
     /// Quota checker will check redis every `redis_check_interval`
     #[serde(
         default = "ConfigGrpc::default_quota_check_interval",
@@ -257,9 +381,71 @@ pub struct ConfigGrpc {
         deserialize_with = "deserialize_int_str"
     )]
     pub quota_check_batch_size: usize,
+    /// What to do with new subscriptions and active streams of a team when its quota
+    /// can't be fetched and no recent enough cached value is left
+    #[serde(default)]
+    pub quota_unavailable_policy: QuotaFailurePolicy,
+    /// Share of the quota at which a warning is pushed into the streams of the team
+    #[serde(default = "ConfigGrpc::default_quota_warning_thresholds")]
+    pub quota_warning_thresholds: Vec<f64>,
+    /// Count usage per team locally and enforce limits from the quota backend
+    #[serde(default)]
+    pub usage_metering: bool,
+    /// Usage meter will compare usage with team limits every `usage_check_interval`
+    #[serde(
+        default = "ConfigGrpc::default_usage_check_interval",
+        with = "humantime_serde"
+    )]
+    pub usage_check_interval: Duration,
+    /// Usage meter will write usage to the quota backend every `usage_flush_interval`
+    #[serde(
+        default = "ConfigGrpc::default_usage_flush_interval",
+        with = "humantime_serde"
+    )]
+    pub usage_flush_interval: Duration,
+    /// Maximum number of concurrent streams of a team, unless overridden by team limits
+    #[serde(default)]
+    pub max_streams_per_team: Option<usize>,
+    /// Maximum number of concurrent streams of an app, unless overridden by team limits
+    #[serde(default)]
+    pub max_streams_per_app: Option<usize>,
+    /// Bandwidth shared by all streams of a team, unless overridden by team limits
+    #[serde(default)]
+    pub throttle_bytes_per_second: Option<u64>,
+    /// Token bucket size, one second of `throttle_bytes_per_second` by default
+    #[serde(default)]
+    pub throttle_burst_bytes: Option<u64>,
+    /// What to do with a stream once the team runs out of bandwidth
+    #[serde(default)]
+    pub throttle_policy: ConfigThrottlePolicy,
+    /// Message types dropped by the `drop_low_priority` policy
+    #[serde(default = "ConfigGrpc::default_throttle_low_priority")]
+    pub throttle_low_priority: Vec<String>,
 }
 
 impl ConfigGrpc {
+    /// Checks combinations of options which can't be expressed by their types
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.x_token.is_none() || self.auth.tokens_path.is_none(),
+            "x_token and auth.tokens_path can't be used together"
+        );
+        anyhow::ensure!(
+            self.redis_push_mode == ConfigRedisPushMode::Disabled
+                || (self.redis_sentinel.is_none() && self.redis_cluster_urls.is_empty()),
+            "redis_push_mode requires redis_url, push events are not supported with redis_sentinel and redis_cluster_urls"
+        );
+        anyhow::ensure!(
+            self.throttle_bytes_per_second != Some(0),
+            "throttle_bytes_per_second can't be 0"
+        );
+        anyhow::ensure!(
+            self.throttle_burst_bytes != Some(0),
+            "throttle_burst_bytes can't be 0"
+        );
+        Ok(())
+    }
+
     const fn max_decoding_message_size_default() -> usize {
         4 * 1024 * 1024
     }
@@ -308,10 +494,38 @@ impl ConfigGrpc {
         10_000
     }
 
+    const fn default_billing_send_batch_size() -> usize {
+        1_000
+    }
+
+    const fn default_billing_file_rotate_size() -> u64 {
+        128 * 1024 * 1024
+    }
+
+    const fn default_billing_aggregation_window() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    const fn default_billing_spool_segment_size() -> u64 {
+        16 * 1024 * 1024
+    }
+
+    const fn default_billing_spool_replay_interval() -> Duration {
+        Duration::from_secs(10)
+    }
+
     const fn default_redis_cache_ttl() -> Duration {
         Duration::from_secs(30)
     }
 
+    const fn default_redis_cache_negative_ttl() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    const fn default_redis_cache_max_stale() -> Duration {
+        Duration::from_secs(300)
+    }
+
     const fn default_redis_cache_capacity() -> usize {
         10_000
     }
@@ -327,6 +541,226 @@ impl ConfigGrpc {
     const fn default_quota_check_batch_size() -> usize {
         1_000
     }
+
+    fn default_throttle_low_priority() -> Vec<String> {
+        ["account", "transaction", "transactionStatus", "entry", "block"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect()
+    }
+
+    fn default_quota_warning_thresholds() -> Vec<f64> {
+        vec![0.8, 0.95]
+    }
+
+    const fn default_usage_check_interval() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    const fn default_usage_flush_interval() -> Duration {
+        Duration::from_secs(10)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigBillingSink {
+    /// Send events to `billing_kafka_topic`
+    #[default]
+    Kafka,
+    /// Write events as JSON lines to rotated files in `billing_file_dir`
+    Jsonl,
+    /// Keep events in memory, for tests
+    Memory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ConfigKafkaSecurityProtocol {
+    Plaintext,
+    Ssl,
+    SaslPlaintext,
+    SaslSsl,
+}
+
+impl ConfigKafkaSecurityProtocol {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Plaintext => "PLAINTEXT",
+            Self::Ssl => "SSL",
+            Self::SaslPlaintext => "SASL_PLAINTEXT",
+            Self::SaslSsl => "SASL_SSL",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ConfigKafkaSaslMechanism {
+    #[serde(rename = "PLAIN")]
+    Plain,
+    #[serde(rename = "SCRAM-SHA-256")]
+    ScramSha256,
+    #[default]
+    #[serde(rename = "SCRAM-SHA-512")]
+    ScramSha512,
+}
+
+impl ConfigKafkaSaslMechanism {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Plain => "PLAIN",
+            Self::ScramSha256 => "SCRAM-SHA-256",
+            Self::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ConfigAuth {
+    /// JSON or TOML file of API tokens sent as `x-token`, each with an identity and
+    /// a filter limits profile, can't be used together with `x_token`
+    pub tokens_path: Option<PathBuf>,
+    /// JWKS file with the keys of `authorization: Bearer <jwt>` tokens, HS256, RS256
+    /// and EdDSA are supported, the file is reloaded once it changes
+    pub jwks_path: Option<PathBuf>,
+    /// How often the token and JWKS files are checked for changes
+    #[serde(with = "humantime_serde")]
+    pub reload_interval: Duration,
+    /// Required `iss` claim
+    pub issuer: Option<String>,
+    /// Required `aud` claim
+    pub audience: Option<String>,
+    /// Allowed clock skew for `exp` and `nbf` claims
+    #[serde(with = "humantime_serde")]
+    pub leeway: Duration,
+    /// Take team, app and network from `x-alchemy-*` headers of requests without a token,
+    /// only safe if all traffic passes through a proxy that sets these headers
+    pub trusted_proxy: bool,
+    /// Identities of client certificates by DNS or URI SAN, or subject common name
+    pub client_certs: HashMap<String, ConfigClientCert>,
+}
+
+impl Default for ConfigAuth {
+    fn default() -> Self {
+        Self {
+            tokens_path: None,
+            jwks_path: None,
+            reload_interval: Duration::from_secs(30),
+            issuer: None,
+            audience: None,
+            leeway: Duration::from_secs(60),
+            trusted_proxy: false,
+            client_certs: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigGrpcAdmin {
+    pub address: SocketAddr,
+    /// Required `x-token` of admin requests
+    pub x_token: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ConfigHealth {
+    /// Not serving once no new processed slot reached client queues for this long,
+    /// counted from the plugin load until the first slot, `/health` fails too
+    #[serde(with = "humantime_serde")]
+    pub max_slot_age: Duration,
+    /// Not serving once the processed slot is this many slots behind the highest slot
+    /// received from Geyser, not checked if unset
+    pub max_slot_lag: Option<u64>,
+    #[serde(with = "humantime_serde")]
+    pub check_interval: Duration,
+}
+
+impl Default for ConfigHealth {
+    fn default() -> Self {
+        Self {
+            max_slot_age: Duration::from_secs(30),
+            max_slot_lag: None,
+            check_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigNetwork {
+    /// Value of `x-alchemy-network` accepted by `subscribe`, e.g. `SOLANA_MAINNET`,
+    /// streams for any other network are rejected
+    pub name: String,
+    /// Ledger directory of the validator, its `genesis.bin` is hashed on startup
+    #[serde(default)]
+    pub ledger_path: Option<PathBuf>,
+    /// Expected genesis hash, the known hash of `SOLANA_MAINNET`, `SOLANA_DEVNET`
+    /// and `SOLANA_TESTNET` if not set
+    #[serde(default)]
+    pub expected_genesis_hash: Option<String>,
+    /// Serve the network without checking the genesis hash, startup fails without
+    /// `ledger_path` otherwise
+    #[serde(default)]
+    pub skip_genesis_check: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigThrottlePolicy {
+    /// Hold back sends until the bucket refills, up to `channel_capacity` messages
+    /// are queued, the stream is closed as lagged beyond that
+    #[default]
+    Delay,
+    /// Drop `throttle_low_priority` message types, keep sending the rest
+    DropLowPriority,
+    /// Close the stream with `RESOURCE_EXHAUSTED`
+    Disconnect,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigQuotaBackend {
+    /// Read `{redis_prefix}:{YYYY-MM}:{team_id}` keys from Redis, Sentinel or Cluster
+    #[default]
+    Redis,
+    /// Nothing is capped unless set through the library API, for tests
+    Memory,
+    /// Read capped teams by period from `quota_file_path`
+    File,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigRedisSentinel {
+    /// Sentinel nodes, e.g. `redis://sentinel-1:26379`
+    pub urls: Vec<String>,
+    pub master_name: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigRedisPushMode {
+    /// Only poll
+    #[default]
+    Disabled,
+    /// Subscribe to `redis_push_channel`, messages are `{YYYY-MM}:{team_id}` key suffixes
+    Pubsub,
+    /// Subscribe to keyspace notifications of `{redis_prefix}:*` keys,
+    /// requires `notify-keyspace-events` to include `K` and `$`
+    Keyspace,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaFailurePolicy {
+    /// Treat the team as not capped
+    #[default]
+    FailOpen,
+    /// Treat the team as capped
+    FailClosed,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -334,6 +768,33 @@ impl ConfigGrpc {
 pub struct ConfigGrpcServerTls {
     pub cert_path: String,
     pub key_path: String,
+    /// PEM file with CA certificates, clients have to present a certificate signed
+    /// by one of them, see `auth.client_certs` for team identities
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+    /// Accept clients without a certificate too, they are authenticated by headers
+    #[serde(default)]
+    pub client_auth_optional: bool,
+    /// How often certificate, key and client CA files are checked for changes
+    #[serde(
+        default = "ConfigGrpcServerTls::default_reload_interval",
+        with = "humantime_serde"
+    )]
+    pub reload_interval: Duration,
+}
+
+impl ConfigGrpcServerTls {
+    const fn default_reload_interval() -> Duration {
+        Duration::from_secs(60)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigClientCert {
+    pub team_id: String,
+    pub app_id: String,
+    pub network: String,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -429,3 +890,59 @@ where
         None => Ok(None),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    fn load(grpc: serde_json::Value) -> PluginResult<Config> {
+        let mut config = json!({
+            "address": "127.0.0.1:10000",
+            "network": { "name": "SOLANA_MAINNET", "skip_genesis_check": true },
+        });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(grpc.as_object().unwrap().clone());
+        Config::load_from_str(&json!({ "libpath": "", "grpc": config }).to_string())
+    }
+
+    #[test]
+    fn test_redis_push_mode() {
+        assert!(load(json!({
+            "redis_url": "redis://127.0.0.1:6379",
+            "redis_push_mode": "pubsub",
+        }))
+        .is_ok());
+        assert!(load(json!({
+            "redis_sentinel": { "urls": ["redis://127.0.0.1:26379"], "master_name": "main" },
+            "redis_push_mode": "keyspace",
+        }))
+        .is_err());
+        assert!(load(json!({
+            "redis_cluster_urls": ["redis://127.0.0.1:7000"],
+            "redis_push_mode": "pubsub",
+        }))
+        .is_err());
+        assert!(load(json!({
+            "redis_cluster_urls": ["redis://127.0.0.1:7000"],
+        }))
+        .is_ok());
+    }
+
+    #[test]
+    fn test_x_token_with_tokens_path() {
+        assert!(load(json!({
+            "x_token": "token",
+            "auth": { "tokens_path": "tokens.json" },
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn test_throttle_zero_rate() {
+        assert!(load(json!({ "throttle_bytes_per_second": 1000 })).is_ok());
+        assert!(load(json!({ "throttle_bytes_per_second": 0 })).is_err());
+        assert!(load(json!({ "throttle_burst_bytes": 0 })).is_err());
+    }
+}
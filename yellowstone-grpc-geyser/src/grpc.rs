@@ -1,18 +1,37 @@
 use {
     crate::{
-        config::{ConfigGrpc, ConfigTokio},
-        kafka_producer_service::{BillingEvent, KafkaProducerService},
-        metrics::{self, DebugClientMessage},
-        redis::{
-            redis_quota_checker::start_redis_quota_checker,
-            refreshing_fallback_cache::RefreshingFallbackCache,
+        admin::GeyserAdminService,
+        auth::{Authenticator, ClientIdentity},
+        billing::{
+            client_billing::{BillingSource, ClientBilling},
+            billing_instance_id, create_billing_sink, create_billing_spool, BillingSender,
+            BillingService, BillingWeights,
+        },
+        config::{ConfigGrpc, ConfigTokio, QuotaFailurePolicy},
+        health::{FeedHealth, HealthMonitor},
+        metrics::{self, DebugClientMessage, DebugClientStatuses, SUBSCRIBE_QUOTA_REJECTED},
+        network::verify_genesis_hash,
+        quota::{
+            create_quota_backend, is_team_capped,
+            quota_checker::QuotaChecker,
+            quota_warner::QuotaWarner,
+            throttle::{
+                ClientThrottle, DelayedMessages, TeamThrottles, ThrottleDecision, ThrottleRate,
+            },
+            usage_meter::UsageMeter,
+            QuotaBackend, QuotaLimits,
+        },
+        redis::redis_quota_subscriber::RedisQuotaSubscriber,
+        reload::{ConfigReloader, LiveConfig},
+        tls::ReloadableTls,
+        user_connection::{
+            connection_manager::{ConnectionInfo, ConnectionManager, ConnectionSignal, StreamLimits},
+            connection_token::ConnectionToken,
         },
-        user_connection::connection_manager::ConnectionManager,
         version::GrpcVersionInfo,
     },
     anyhow::Context,
     log::{error, info},
-    prost::Message as ProstMessage,
     prost_types::Timestamp,
     solana_sdk::{
         clock::{Slot, MAX_RECENT_BLOCKHASHES},
@@ -27,26 +46,25 @@ use {
         time::SystemTime,
     },
     tokio::{
-        fs,
         runtime::Builder,
-        sync::{broadcast, mpsc, oneshot, Mutex, Notify, RwLock, Semaphore},
+        sync::{broadcast, mpsc, oneshot, watch, Mutex, Notify, RwLock, Semaphore},
         task::spawn_blocking,
-        time::{sleep, Duration, Instant},
+        time::{sleep, sleep_until, Duration, Instant},
     },
     tokio_stream::wrappers::ReceiverStream,
     tonic::{
+        server::NamedService,
         service::interceptor::interceptor,
-        transport::{
-            server::{Server, TcpIncoming},
-            Identity, ServerTlsConfig,
-        },
+        transport::server::{Server, TcpIncoming},
         Request, Response, Result as TonicResult, Status, Streaming,
     },
-    tonic_health::server::health_reporter,
+    tonic_health::{
+        server::{health_reporter, HealthReporter},
+        ServingStatus,
+    },
     yellowstone_grpc_proto::{
         plugin::{
             filter::{
-                limits::FilterLimits,
                 message::{FilteredUpdate, FilteredUpdateOneof},
                 name::FilterNames,
                 Filter,
@@ -261,7 +279,7 @@ struct MessageId {
 }
 
 impl MessageId {
-    fn next(&mut self) -> u64 {
+    const fn next(&mut self) -> u64 {
         self.id = self.id.checked_add(1).expect("message id overflow");
         self.id
     }
@@ -342,7 +360,7 @@ type ReplayStoredSlotsRequest = (CommitmentLevel, Slot, oneshot::Sender<Replayed
 pub struct GrpcService {
     config_snapshot_client_channel_capacity: usize,
     config_channel_capacity: usize,
-    config_filter_limits: Arc<FilterLimits>,
+    live_config: watch::Receiver<Arc<LiveConfig>>,
     blocks_meta: Option<BlockMetaStorage>,
     subscribe_id: AtomicUsize,
     snapshot_rx: Mutex<Option<crossbeam_channel::Receiver<Box<Message>>>>,
@@ -351,9 +369,15 @@ pub struct GrpcService {
     replay_first_available_slot: Option<Arc<AtomicU64>>,
     debug_clients_tx: Option<mpsc::UnboundedSender<DebugClientMessage>>,
     filter_names: Arc<Mutex<FilterNames>>,
-    billing_tx: mpsc::Sender<BillingEvent>,
-    billing_ticker_interval: Duration,
+    billing_tx: BillingSender,
+    billing_instance_id: Arc<str>,
     connection_manager: Arc<ConnectionManager>,
+    quota_backend: Arc<dyn QuotaBackend>,
+    /// Only set with `usage_metering`, nothing would trim the teams of the meter otherwise
+    usage_meter: Option<Arc<UsageMeter>>,
+    authenticator: Arc<Authenticator>,
+    team_throttles: Arc<TeamThrottles>,
+    network: String,
 }
 
 impl GrpcService {
@@ -362,12 +386,19 @@ impl GrpcService {
         config_tokio: ConfigTokio,
         config: ConfigGrpc,
         debug_clients_tx: Option<mpsc::UnboundedSender<DebugClientMessage>>,
+        debug_clients: Option<Arc<DebugClientStatuses>>,
+        config_reloader: Arc<ConfigReloader>,
+        health: Arc<HealthMonitor>,
         is_reload: bool,
     ) -> anyhow::Result<(
         Option<crossbeam_channel::Sender<Box<Message>>>,
         mpsc::UnboundedSender<Message>,
         Arc<Notify>,
     )> {
+        verify_genesis_hash(&config.network)
+            .await
+            .context("failed to verify network")?;
+
         // Bind service address
         let incoming = TcpIncoming::new(
             config.address,
@@ -405,18 +436,17 @@ impl GrpcService {
                 (Some(Arc::new(AtomicU64::new(u64::MAX))), Some(tx), Some(rx))
             };
 
-        // gRPC server builder with optional TLS
+        // gRPC server builder with optional TLS, connections are accepted by
+        // `ReloadableTls` so certificates can be replaced without a restart
+        let tls = match &config.tls_config {
+            Some(tls_config) => Some(Arc::new(
+                ReloadableTls::new(tls_config.clone())
+                    .await
+                    .context("failed to apply tls_config")?,
+            )),
+            None => None,
+        };
         let mut server_builder = Server::builder();
-        if let Some(tls_config) = &config.tls_config {
-            let (cert, key) = tokio::try_join!(
-                fs::read(&tls_config.cert_path),
-                fs::read(&tls_config.key_path)
-            )
-            .context("failed to load tls_config files")?;
-            server_builder = server_builder
-                .tls_config(ServerTlsConfig::new().identity(Identity::from_pem(cert, key)))
-                .context("failed to apply tls_config")?;
-        }
         if let Some(enabled) = config.server_http2_adaptive_window {
             server_builder = server_builder.http2_adaptive_window(Some(enabled));
         }
@@ -440,43 +470,86 @@ impl GrpcService {
             config.filter_names_cleanup_interval,
         )));
 
-        let (kafka_service, kafka_task) = KafkaProducerService::new(
-            &config.billing_kafka_brokers,
-            config.billing_kafka_username.as_deref(),
-            config.billing_kafka_password.as_deref(),
-            config.billing_kafka_topic.clone(),
-            config.billing_kafka_send_queue_timeout,
+        let billing_sink = create_billing_sink(&config)
+            .await
+            .context("failed to create billing sink")?;
+        let billing_spool = create_billing_spool(&config)?;
+        let billing_instance_id: Arc<str> = billing_instance_id(&config).into();
+        let (billing_service, billing_task) = BillingService::new(
+            billing_sink,
+            billing_spool,
+            Arc::clone(&billing_instance_id),
             config.billing_kafka_send_channel_size,
+            config.billing_send_batch_size,
+            config.billing_spool_replay_interval,
+            (!config.billing_per_connection_events).then_some(config.billing_aggregation_window),
+            BillingWeights::new(config.billing_weights.clone()),
         );
 
-        let quota_cache = Arc::new(
-            RefreshingFallbackCache::new(
-                &config.redis_url,
-                config.redis_prefix.clone(),
-                config.redis_cache_ttl,
-                config.redis_cache_capacity,
-                config.redis_background_buffer,
-                Arc::new(|opt| matches!(opt.as_deref(), Some("CAPPED"))),
-            )
+        let quota_backend = create_quota_backend(&config)
             .await
-            .expect("failed to initialize quota cache"),
-        );
+            .context("failed to create quota backend")?;
 
         let connection_manager = Arc::new(ConnectionManager::new());
 
-        tokio::spawn(start_redis_quota_checker(
-            connection_manager.clone(),
-            quota_cache.clone(),
-            config.quota_check_interval,
-            config.quota_check_batch_size,
+        let quota_warner = Arc::new(QuotaWarner::new(
+            config_reloader.subscribe(),
+            Arc::clone(&connection_manager),
         ));
+        let quota_checker = Arc::new(QuotaChecker::new(
+            Arc::clone(&connection_manager),
+            Arc::clone(&quota_backend),
+            Arc::clone(&quota_warner),
+            config_reloader.subscribe(),
+        ));
+        tokio::spawn(Arc::clone(&quota_checker).run());
+
+        // Admin server, stopped together with the gRPC server
+        let admin_shutdown = Arc::new(Notify::new());
+        if let Some(config_admin) = config.admin.clone() {
+            let debug_clients =
+                debug_clients.context("admin service requires debug clients statuses")?;
+            let admin = GeyserAdminService::new(
+                Arc::clone(&connection_manager),
+                debug_clients,
+                Arc::clone(&quota_checker),
+                Arc::clone(&config_reloader),
+            );
+            tokio::spawn(admin.serve(config_admin, Arc::clone(&admin_shutdown)));
+        }
+        if let Some(subscriber) = RedisQuotaSubscriber::new(&config)? {
+            tokio::spawn(subscriber.run(
+                Arc::clone(&quota_backend),
+                Arc::clone(&connection_manager),
+                Arc::clone(&quota_warner),
+            ));
+        }
+
+        let authenticator = Arc::new(
+            Authenticator::new(&config.auth)
+                .await
+                .context("failed to create authenticator")?,
+        );
+        tokio::spawn(Arc::clone(&authenticator).run_reload(config.auth.reload_interval));
+
+        let usage_meter = config.usage_metering.then(|| {
+            let usage_meter = Arc::new(UsageMeter::new());
+            tokio::spawn(Arc::clone(&usage_meter).run(
+                Arc::clone(&quota_backend),
+                Arc::clone(&connection_manager),
+                quota_warner,
+                config.usage_check_interval,
+                config.usage_flush_interval,
+            ));
+            usage_meter
+        });
 
         // Create Server
         let max_decoding_message_size = config.max_decoding_message_size;
         let mut service = GeyserServer::new(Self {
             config_snapshot_client_channel_capacity: config.snapshot_client_channel_capacity,
             config_channel_capacity: config.channel_capacity,
-            config_filter_limits: Arc::new(config.filter_limits),
+            live_config: config_reloader.subscribe(),
             blocks_meta,
             subscribe_id: AtomicUsize::new(0),
             snapshot_rx: Mutex::new(snapshot_rx),
@@ -485,9 +558,17 @@ impl GrpcService {
             replay_first_available_slot: replay_first_available_slot.clone(),
             debug_clients_tx,
             filter_names,
-            billing_tx: kafka_service.sender.clone(),
-            billing_ticker_interval: config.billing_ticker_interval,
+            billing_tx: billing_service.sender.clone(),
+            billing_instance_id,
             connection_manager,
+            quota_backend,
+            usage_meter,
+            authenticator,
+            team_throttles: Arc::new(TeamThrottles::new(
+                config.throttle_policy,
+                config.throttle_low_priority,
+            )),
+            network: config.network.name.clone(),
         })
         .max_decoding_message_size(max_decoding_message_size);
         for encoding in config.compression.accept {
@@ -527,14 +608,15 @@ impl GrpcService {
         // Run Server
         let shutdown = Arc::new(Notify::new());
         let shutdown_grpc = Arc::clone(&shutdown);
+        let live_config = config_reloader.subscribe();
         tokio::spawn(async move {
             // gRPC Health check service
-            let (mut health_reporter, health_service) = health_reporter();
-            health_reporter.set_serving::<GeyserServer<Self>>().await;
+            let (health_reporter, health_service) = health_reporter();
+            tokio::spawn(Self::report_health(health_reporter, health.subscribe()));
 
-            server_builder
+            let router = server_builder
                 .layer(interceptor(move |request: Request<()>| {
-                    if let Some(x_token) = &config.x_token {
+                    if let Some(x_token) = &live_config.borrow().x_token {
                         match request.metadata().get("x-token") {
                             Some(token) if x_token == token => Ok(request),
                             _ => Err(Status::unauthenticated("No valid auth token")),
@@ -544,25 +626,64 @@ impl GrpcService {
                     }
                 }))
                 .add_service(health_service)
-                .add_service(service)
-                .serve_with_incoming_shutdown(incoming, shutdown_grpc.notified())
-                .await
-                .ok();
+                .add_service(service);
+            match tls {
+                Some(tls) => {
+                    router
+                        .serve_with_incoming_shutdown(
+                            tls.incoming(incoming),
+                            shutdown_grpc.notified(),
+                        )
+                        .await
+                }
+                None => {
+                    router
+                        .serve_with_incoming_shutdown(incoming, shutdown_grpc.notified())
+                        .await
+                }
+            }
+            .ok();
+            admin_shutdown.notify_one();
         });
 
         let shutdown_clone = Arc::clone(&shutdown);
         tokio::spawn(async move {
             shutdown_clone.notified().await;
 
-            match kafka_task.await {
-                Ok(_) => info!("Kafka task shut down cleanly."),
-                Err(e) => error!("Kafka task shutdown with error: {:?}", e),
+            match billing_task.await {
+                Ok(_) => info!("Billing task shut down cleanly."),
+                Err(e) => error!("Billing task shutdown with error: {:?}", e),
             }
         });
 
         Ok((snapshot_tx, messages_tx, shutdown))
     }
 
+    /// Sets both the overall and `geyser.Geyser` status, load balancers may check either
+    async fn report_health(
+        mut health_reporter: HealthReporter,
+        mut health_rx: watch::Receiver<FeedHealth>,
+    ) {
+        let mut reported = None;
+        loop {
+            let status = if health_rx.borrow_and_update().is_serving() {
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+            if reported != Some(status) {
+                health_reporter.set_service_status("", status).await;
+                health_reporter
+                    .set_service_status(<GeyserServer<Self> as NamedService>::NAME, status)
+                    .await;
+                reported = Some(status);
+            }
+            if health_rx.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+
     async fn geyser_loop(
         mut messages_rx: mpsc::UnboundedReceiver<Message>,
         blocks_meta_tx: Option<mpsc::UnboundedSender<Message>>,
@@ -910,24 +1031,22 @@ impl GrpcService {
         replay_stored_slots_tx: Option<mpsc::Sender<ReplayStoredSlotsRequest>>,
         debug_client_tx: Option<mpsc::UnboundedSender<DebugClientMessage>>,
         drop_client: impl FnOnce(),
-        team_id: String,
-        app_id: String,
-        network: String,
-        billing_tx: mpsc::Sender<BillingEvent>,
+        mut connection_token: ConnectionToken,
+        throttle: Option<ClientThrottle>,
+        mut billing: ClientBilling,
         billing_ticker_interval: Duration,
-        connection_manager: Arc<ConnectionManager>,
     ) {
-        let mut bytes_sent_by_type: HashMap<&'static str, u64> = HashMap::new();
-
         let mut billing_ticker = tokio::time::interval(billing_ticker_interval);
 
         let mut filter = Filter::default();
         metrics::update_subscriptions(&endpoint, None, Some(&filter));
 
         metrics::connections_total_inc();
-        DebugClientMessage::maybe_send(&debug_client_tx, || DebugClientMessage::UpdateFilter {
+        DebugClientMessage::maybe_send(&debug_client_tx, || DebugClientMessage::Connected {
             id,
+            info: Arc::clone(connection_token.info()),
             filter: Box::new(filter.clone()),
+            queue_capacity: stream_tx.max_capacity(),
         });
         info!("client #{id}: new");
 
@@ -941,12 +1060,17 @@ impl GrpcService {
                 snapshot_rx,
                 &mut is_alive,
                 &mut filter,
+                &mut billing,
             )
             .await;
         }
 
-        let mut connection_token = connection_manager.register_team(team_id.clone());
+        let mut delayed = DelayedMessages::new(stream_tx.max_capacity());
         let shutdown_rx = connection_token.shutdown_rx();
+        // deliver a warning which was signalled before this client joined
+        if *shutdown_rx.borrow() != ConnectionSignal::Active {
+            shutdown_rx.mark_changed();
+        }
 
         if is_alive {
             'outer: loop {
@@ -1016,6 +1140,26 @@ impl GrpcService {
                                     messages.sort_by_key(|msg| msg.0);
                                     for (_msgid, message) in messages.iter() {
                                         for message in filter.get_updates(message, Some(commitment)) {
+                                            let size = prost::Message::encoded_len(&message) as u64;
+                                            let delay = match Self::admit(throttle.as_ref(), &message, size) {
+                                                ThrottleDecision::Send => Duration::ZERO,
+                                                ThrottleDecision::Delay(delay) => delay,
+                                                ThrottleDecision::Drop => continue,
+                                                ThrottleDecision::Disconnect => {
+                                                    let _ = stream_tx.send(Err(Status::resource_exhausted("connection closed: bandwidth limit exceeded"))).await;
+                                                    break 'outer;
+                                                }
+                                            };
+                                            // keeps the order with messages delayed before
+                                            if !delay.is_zero() || !delayed.is_empty() {
+                                                if delayed.push(delay, (BillingSource::Replay, message, size)).is_err() {
+                                                    error!("client #{id}: lagged to send an update");
+                                                    let _ = stream_tx.send(Err(Status::internal("lagged to send an update"))).await;
+                                                    break 'outer;
+                                                }
+                                                continue;
+                                            }
+                                            billing.record_sized(BillingSource::Replay, &message, size);
                                             match stream_tx.send(Ok(message)).await {
                                                 Ok(()) => {}
                                                 Err(mpsc::error::SendError(_)) => {
@@ -1054,11 +1198,31 @@ impl GrpcService {
                         if commitment == filter.get_commitment_level() {
                             for (_msgid, message) in messages.iter() {
                                 for message in filter.get_updates(message, Some(commitment)) {
-                                    let message_type = message.message.subscription_type();
-                                    if message_type != "ping" && message_type != "pong" {
-                                        let size = ProstMessage::encoded_len(&message) as u64;
-                                        *bytes_sent_by_type.entry(message_type).or_default() += size;
+                                    let size = prost::Message::encoded_len(&message) as u64;
+                                    let delay = match Self::admit(throttle.as_ref(), &message, size) {
+                                        ThrottleDecision::Send => Duration::ZERO,
+                                        ThrottleDecision::Delay(delay) => delay,
+                                        ThrottleDecision::Drop => continue,
+                                        ThrottleDecision::Disconnect => {
+                                            info!("client #{id}: team is over its bandwidth limit");
+                                            tokio::spawn(async move {
+                                                let _ = stream_tx.send(Err(Status::resource_exhausted("connection closed: bandwidth limit exceeded"))).await;
+                                            });
+                                            break 'outer;
+                                        }
+                                    };
+                                    // keeps the order with messages delayed before
+                                    if !delay.is_zero() || !delayed.is_empty() {
+                                        if delayed.push(delay, (BillingSource::Live, message, size)).is_err() {
+                                            error!("client #{id}: lagged to send an update");
+                                            tokio::spawn(async move {
+                                                let _ = stream_tx.send(Err(Status::internal("lagged to send an update"))).await;
+                                            });
+                                            break 'outer;
+                                        }
+                                        continue;
                                     }
+                                    billing.record_sized(BillingSource::Live, &message, size);
 
                                     match stream_tx.try_send(Ok(message)) {
                                         Ok(()) => {}
@@ -1081,49 +1245,73 @@ impl GrpcService {
                         if commitment == CommitmentLevel::Processed && debug_client_tx.is_some() {
                             for message in messages.iter() {
                                 if let Message::Slot(slot_message) = &message.1 {
-                                    DebugClientMessage::maybe_send(&debug_client_tx, || DebugClientMessage::UpdateSlot { id, slot: slot_message.slot });
+                                    DebugClientMessage::maybe_send(&debug_client_tx, || DebugClientMessage::UpdateSlot {
+                                        id,
+                                        slot: slot_message.slot,
+                                        queue_depth: stream_tx.max_capacity() - stream_tx.capacity(),
+                                        bytes_sent: billing.bytes_sent(),
+                                    });
                                 }
                             }
                         }
                     }
-                    // If a billing ticker is received, it will be used to update the billing
-                    _ = billing_ticker.tick() => {
-                        for (message_type, size) in bytes_sent_by_type.drain() {
-                            let event = BillingEvent {
-                                app_id: app_id.clone(),
-                                team_id: team_id.clone(),
-                                eth_method: message_type.to_string(),
-                                eth_network: network.clone(),
-                                subscription_id: format!("grpc-client-{}", id),
-                                subscription_type: message_type.to_string(),
-                                log_source: "grpc".to_string(),
-                                response_content_length: size,
-                            };
-
-                            if let Err(err) = billing_tx.try_send(event) {
-                                error!("Billing channel full or closed for client #{id}: {err}");
+                    // Send messages of the `delay` throttle policy once their time has come
+                    _ = sleep_until(delayed.ready_at().unwrap_or_else(Instant::now)), if !delayed.is_empty() => {
+                        while let Some((source, message, size)) = delayed.pop_ready(Instant::now()) {
+                            billing.record_sized(source, &message, size);
+                            match stream_tx.try_send(Ok(message)) {
+                                Ok(()) => {}
+                                Err(mpsc::error::TrySendError::Full(_)) => {
+                                    error!("client #{id}: lagged to send an update");
+                                    tokio::spawn(async move {
+                                        let _ = stream_tx.send(Err(Status::internal("lagged to send an update"))).await;
+                                    });
+                                    break 'outer;
+                                }
+                                Err(mpsc::error::TrySendError::Closed(_)) => {
+                                    error!("client #{id}: stream closed");
+                                    break 'outer;
+                                }
                             }
                         }
                     }
+                    // If a billing ticker is received, it will be used to update the billing
+                    _ = billing_ticker.tick() => {
+                        billing.flush();
+                    }
 
                     changed = shutdown_rx.changed() => {
-                        match changed {
-                            Ok(_) if *shutdown_rx.borrow() => {
-                                info!("Shutdown signal received for client #{id} - geyser task");
-                                let _ = stream_tx.send(Err(Status::resource_exhausted("connection closed: quota exceeded"))).await;
+                        if changed.is_err() {
+                            info!("Shutdown sender dropped for client #{id}");
+                            break 'outer;
+                        }
+                        let signal = shutdown_rx.borrow_and_update().clone();
+                        match signal {
+                            ConnectionSignal::Shutdown(reason) => {
+                                info!("Shutdown signal received for client #{id} - geyser task: {}", reason.message);
+                                let _ = stream_tx.send(Err(reason.to_status())).await;
                                 break 'outer;
                             }
-                            Err(_) => {
-                                info!("Shutdown sender dropped for client #{id}");
-                                break 'outer;
+                            ConnectionSignal::QuotaWarning(warning) => {
+                                let message = FilteredUpdate::new_empty(FilteredUpdateOneof::quota_warning(
+                                    warning.usage_ratio,
+                                    warning.threshold,
+                                ));
+                                if stream_tx.send(Ok(message)).await.is_err() {
+                                    error!("client #{id}: stream closed");
+                                    break 'outer;
+                                }
                             }
-                            _ => {} // Changed but still `false` — keep going
+                            ConnectionSignal::Active => {}
                         }
                     }
                 }
             }
         }
 
+        // bill everything sent since the last tick, whatever the reason to stop
+        billing.flush();
+
         metrics::connections_total_dec();
         DebugClientMessage::maybe_send(&debug_client_tx, || DebugClientMessage::Removed { id });
         metrics::update_subscriptions(&endpoint, Some(&filter), None);
@@ -1131,6 +1319,7 @@ impl GrpcService {
         drop_client();
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn client_loop_snapshot(
         id: usize,
         endpoint: &str,
@@ -1139,6 +1328,7 @@ impl GrpcService {
         snapshot_rx: crossbeam_channel::Receiver<Box<Message>>,
         is_alive: &mut bool,
         filter: &mut Filter,
+        billing: &mut ClientBilling,
     ) {
         info!("client #{id}: going to receive snapshot data");
 
@@ -1185,6 +1375,7 @@ impl GrpcService {
             };
 
             for message in filter.get_updates(&message, None) {
+                billing.record(BillingSource::Snapshot, &message);
                 if stream_tx.send(Ok(message)).await.is_err() {
                     error!("client #{id}: stream closed");
                     *is_alive = false;
@@ -1193,6 +1384,58 @@ impl GrpcService {
             }
         }
     }
+
+    fn admit(
+        throttle: Option<&ClientThrottle>,
+        message: &FilteredUpdate,
+        size: u64,
+    ) -> ThrottleDecision {
+        match throttle {
+            Some(throttle) => throttle.admit(message.message.subscription_type(), size),
+            None => ThrottleDecision::Send,
+        }
+    }
+
+    /// Limits of the team, missing limits fall back to the config
+    async fn team_limits(&self, team_id: &str) -> QuotaLimits {
+        let team_ids = [team_id.to_owned()];
+        match self.quota_backend.limits(&team_ids).await.remove(team_id) {
+            Some(Ok(limits)) => limits.unwrap_or_default(),
+            Some(Err(error)) => {
+                error!("failed to fetch limits for team {team_id}: {error:?}");
+                QuotaLimits::default()
+            }
+            None => QuotaLimits::default(),
+        }
+    }
+
+    fn stream_limits(live_config: &LiveConfig, team_limits: &QuotaLimits) -> StreamLimits {
+        StreamLimits {
+            per_team: team_limits
+                .max_streams
+                .or(live_config.stream_limits.per_team),
+            per_app: team_limits
+                .max_streams_per_app
+                .or(live_config.stream_limits.per_app),
+        }
+    }
+
+    fn throttle_rate(live_config: &LiveConfig, team_limits: &QuotaLimits) -> Option<ThrottleRate> {
+        // a zero rate would hold messages back forever, it's treated as unset
+        let bytes_per_second = team_limits
+            .bytes_per_second
+            .filter(|rate| *rate > 0)
+            .or(live_config.throttle_bytes_per_second)?;
+        let burst_bytes = team_limits
+            .burst_bytes
+            .filter(|burst| *burst > 0)
+            .or(live_config.throttle_burst_bytes)
+            .unwrap_or(bytes_per_second);
+        Some(ThrottleRate {
+            bytes_per_second,
+            burst_bytes,
+        })
+    }
 }
 
 #[tonic::async_trait]
@@ -1204,6 +1447,75 @@ impl Geyser for GrpcService {
         mut request: Request<Streaming<SubscribeRequest>>,
     ) -> TonicResult<Response<Self::SubscribeStream>> {
         let id = self.subscribe_id.fetch_add(1, Ordering::Relaxed);
+        // all rejections happen before the snapshot is taken, so a rejected request can't consume it
+        let peer_certs = request.peer_certs();
+        let identity = self
+            .authenticator
+            .authenticate(request.metadata(), peer_certs.as_deref().map(Vec::as_slice))?;
+        // the stream keeps the limits it was opened with, a reload applies to new streams
+        let live_config = Arc::clone(&self.live_config.borrow());
+
+        let ClientIdentity {
+            team_id,
+            app_id,
+            network,
+            limits: plan_limits,
+            filter_limits,
+        } = identity;
+        if network != self.network {
+            return Err(Status::invalid_argument(format!(
+                "invalid network: {network}, this endpoint serves {}",
+                self.network
+            )));
+        }
+
+        // Reject capped teams before any task is spawned for the stream
+        let capped = match is_team_capped(self.quota_backend.as_ref(), &team_id).await {
+            Ok(capped) => capped,
+            Err(error) => {
+                error!("client #{id}: failed to check quota for team {team_id}: {error:?}");
+                live_config.quota_unavailable_policy == QuotaFailurePolicy::FailClosed
+            }
+        } || self
+            .usage_meter
+            .as_ref()
+            .is_some_and(|usage_meter| usage_meter.is_hard_limited(&team_id));
+        if capped {
+            SUBSCRIBE_QUOTA_REJECTED.inc();
+            info!("client #{id}: team {team_id} is capped, rejecting subscription");
+            return Err(Status::resource_exhausted("quota exceeded"));
+        }
+
+        let endpoint = request
+            .metadata()
+            .get("x-endpoint")
+            .and_then(|h| h.to_str().ok().map(|s| s.to_string()))
+            .unwrap_or_else(|| "".to_owned());
+
+        let connection_info = ConnectionInfo {
+            client_id: id,
+            team_id: team_id.clone(),
+            app_id: app_id.clone(),
+            endpoint: endpoint.clone(),
+            network: network.clone(),
+            connected_at: SystemTime::now(),
+        };
+        let team_limits = self
+            .team_limits(&team_id)
+            .await
+            .or(plan_limits.unwrap_or_default());
+        let connection_token = self
+            .connection_manager
+            .register_connection(
+                connection_info,
+                Self::stream_limits(&live_config, &team_limits),
+            )
+            .map_err(|error| {
+                info!("client #{id}: team {team_id} app {app_id}: {error}, rejecting subscription");
+                Status::resource_exhausted(error.to_string())
+            })?;
+        let throttle = Self::throttle_rate(&live_config, &team_limits)
+            .map(|rate| self.team_throttles.throttle(&team_id, rate));
 
         let x_request_snapshot = request.metadata().contains_key("x-request-snapshot");
         let snapshot_rx = if x_request_snapshot {
@@ -1224,40 +1536,6 @@ impl Geyser for GrpcService {
         let ping_client_tx = client_tx.clone();
         let ping_exit = Arc::clone(&notify_exit1);
 
-        let team_id = request
-            .metadata()
-            .get("x-alchemy-team-id")
-            .ok_or(Status::invalid_argument("missing team id"))?
-            .to_str()
-            .map_err(|_| Status::invalid_argument("invalid team id"))?
-            .to_string();
-
-        let app_id = request
-            .metadata()
-            .get("x-alchemy-app-id")
-            .ok_or(Status::invalid_argument("missing app id"))?
-            .to_str()
-            .map_err(|_| Status::invalid_argument("invalid app id"))?
-            .to_string();
-
-        let network = match request
-            .metadata()
-            .get("x-alchemy-network")
-            .and_then(|val| val.to_str().ok())
-        {
-            Some("SOLANA_MAINNET") => "SOLANA_MAINNET".to_string(),
-            Some("SOLANA_DEVNET") => "SOLANA_DEVNET".to_string(),
-            Some(other) => {
-                return Err(Status::invalid_argument(format!(
-                    "invalid network: {}",
-                    other
-                )));
-            }
-            None => {
-                return Err(Status::invalid_argument("missing network"));
-            }
-        };
-
         // Spawns the task that sends ping messages to the client
         tokio::spawn(async move {
             let exit = ping_exit.notified();
@@ -1283,13 +1561,7 @@ impl Geyser for GrpcService {
             }
         });
 
-        let endpoint = request
-            .metadata()
-            .get("x-endpoint")
-            .and_then(|h| h.to_str().ok().map(|s| s.to_string()))
-            .unwrap_or_else(|| "".to_owned());
-
-        let config_filter_limits = Arc::clone(&self.config_filter_limits);
+        let filter_limits = filter_limits.unwrap_or_else(|| Arc::clone(&live_config.filter_limits));
         let filter_names = Arc::clone(&self.filter_names);
         let incoming_stream_tx = stream_tx.clone();
         let incoming_client_tx = client_tx;
@@ -1311,7 +1583,7 @@ impl Geyser for GrpcService {
                             let mut filter_names = filter_names.lock().await;
                             filter_names.try_clean();
 
-                            if let Err(error) = match Filter::new(&request, &config_filter_limits, &mut filter_names) {
+                            if let Err(error) = match Filter::new(&request, &filter_limits, &mut filter_names) {
                                 Ok(filter) => {
                                     if let Some(msg) = filter.get_pong_msg() {
                                         if incoming_stream_tx.send(Ok(msg)).await.is_err() {
@@ -1349,7 +1621,17 @@ impl Geyser for GrpcService {
             }
         });
 
-        let connection_manager = Arc::clone(&self.connection_manager);
+        let billing = ClientBilling::new(
+            Arc::clone(&self.billing_instance_id),
+            id,
+            team_id.clone(),
+            app_id,
+            network,
+            self.billing_tx.clone(),
+            self.usage_meter
+                .as_ref()
+                .map(|usage_meter| usage_meter.team(&team_id)),
+        );
         // Spawns the task that listens for messages from solana RPC
         tokio::spawn(Self::client_loop(
             id,
@@ -1364,12 +1646,10 @@ impl Geyser for GrpcService {
                 notify_exit1.notify_one();
                 notify_exit2.notify_one();
             },
-            team_id,
-            app_id,
-            network,
-            self.billing_tx.clone(),
-            self.billing_ticker_interval,
-            connection_manager,
+            connection_token,
+            throttle,
+            billing,
+            live_config.billing_ticker_interval,
         ));
 
         Ok(Response::new(ReceiverStream::new(stream_rx)))
@@ -1476,3 +1756,145 @@ impl Geyser for GrpcService {
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            billing::memory_sink::MemorySink,
+            config::ConfigAuth,
+            quota::{memory_backend::MemoryQuotaBackend, QuotaKey},
+        },
+        tonic::{
+            codec::{Codec, ProstCodec},
+            metadata::MetadataValue,
+        },
+    };
+
+    async fn create_service(
+        config: serde_json::Value,
+        quota_backend: Arc<MemoryQuotaBackend>,
+    ) -> GrpcService {
+        let config: ConfigGrpc = serde_json::from_value(config).unwrap();
+        let (_snapshot_tx, snapshot_rx) = crossbeam_channel::bounded(1);
+        let (billing_service, _billing_task) = BillingService::new(
+            Arc::new(MemorySink::default()),
+            None,
+            "test".into(),
+            16,
+            16,
+            Duration::from_secs(60),
+            None,
+            BillingWeights::default(),
+        );
+        let auth = ConfigAuth {
+            trusted_proxy: true,
+            ..Default::default()
+        };
+        GrpcService {
+            config_snapshot_client_channel_capacity: 16,
+            config_channel_capacity: 16,
+            live_config: watch::channel(Arc::new(LiveConfig::new(&config))).1,
+            blocks_meta: None,
+            subscribe_id: AtomicUsize::new(0),
+            snapshot_rx: Mutex::new(Some(snapshot_rx)),
+            broadcast_tx: broadcast::channel(16).0,
+            replay_stored_slots_tx: None,
+            replay_first_available_slot: None,
+            debug_clients_tx: None,
+            filter_names: Arc::new(Mutex::new(FilterNames::new(
+                config.filter_name_size_limit,
+                config.filter_names_size_limit,
+                config.filter_names_cleanup_interval,
+            ))),
+            billing_tx: billing_service.sender.clone(),
+            billing_instance_id: "test".into(),
+            connection_manager: Arc::new(ConnectionManager::new()),
+            quota_backend,
+            usage_meter: None,
+            authenticator: Arc::new(Authenticator::new(&auth).await.unwrap()),
+            team_throttles: Arc::new(TeamThrottles::new(config.throttle_policy, [])),
+            network: config.network.name,
+        }
+    }
+
+    fn subscribe_request(team_id: &str, network: &str) -> Request<Streaming<SubscribeRequest>> {
+        let mut codec = ProstCodec::<FilteredUpdate, SubscribeRequest>::default();
+        let mut request = Request::new(Streaming::new_request(
+            codec.decoder(),
+            tonic::body::empty_body(),
+            None,
+            None,
+        ));
+        let metadata = request.metadata_mut();
+        metadata.insert("x-alchemy-team-id", MetadataValue::try_from(team_id).unwrap());
+        metadata.insert("x-alchemy-app-id", MetadataValue::from_static("app"));
+        metadata.insert("x-alchemy-network", MetadataValue::try_from(network).unwrap());
+        metadata.insert("x-request-snapshot", MetadataValue::from_static("true"));
+        request
+    }
+
+    #[tokio::test]
+    async fn test_rejected_subscribe_keeps_snapshot() {
+        let quota_backend = Arc::new(MemoryQuotaBackend::default());
+        quota_backend.set_capped(QuotaKey::current("capped"), true);
+        let service = create_service(
+            serde_json::json!({
+                "address": "127.0.0.1:0",
+                "network": { "name": "SOLANA_MAINNET" },
+                "max_streams_per_team": 1,
+            }),
+            quota_backend,
+        )
+        .await;
+
+        // wrong network
+        let status = service
+            .subscribe(subscribe_request("team", "SOLANA_DEVNET"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(service.snapshot_rx.lock().await.is_some());
+
+        // capped team
+        let status = service
+            .subscribe(subscribe_request("capped", "SOLANA_MAINNET"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert!(service.snapshot_rx.lock().await.is_some());
+
+        // stream limit
+        let _token = service
+            .connection_manager
+            .register_connection(
+                ConnectionInfo {
+                    client_id: usize::MAX,
+                    team_id: "team".to_owned(),
+                    app_id: "app".to_owned(),
+                    endpoint: String::new(),
+                    network: "SOLANA_MAINNET".to_owned(),
+                    connected_at: SystemTime::now(),
+                },
+                StreamLimits {
+                    per_team: None,
+                    per_app: None,
+                },
+            )
+            .unwrap();
+        let status = service
+            .subscribe(subscribe_request("team", "SOLANA_MAINNET"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert!(service.snapshot_rx.lock().await.is_some());
+
+        // admitted stream takes the snapshot
+        assert!(service
+            .subscribe(subscribe_request("other", "SOLANA_MAINNET"))
+            .await
+            .is_ok());
+        assert!(service.snapshot_rx.lock().await.is_none());
+    }
+}
@@ -0,0 +1,200 @@
+use {
+    crate::{config::ConfigHealth, metrics},
+    log::{info, warn},
+    solana_sdk::clock::Slot,
+    std::{fmt, sync::Arc},
+    tokio::{
+        sync::watch,
+        time::{interval, Duration, Instant, MissedTickBehavior},
+    },
+    yellowstone_grpc_proto::plugin::message::SlotStatus,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedStatus {
+    /// No processed slot since the plugin was loaded, for up to `max_slot_age`
+    Starting,
+    Healthy,
+    /// No new processed slot within `max_slot_age`, or none at all since the plugin was loaded
+    Stale,
+    /// More than `max_slot_lag` slots behind the highest slot received from Geyser
+    Behind,
+}
+
+/// Freshness of the processed slot sent to client queues
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeedHealth {
+    pub status: FeedStatus,
+    pub processed_slot: Slot,
+    /// Slots between the highest slot received from Geyser and `processed_slot`
+    pub lag: Slot,
+    /// Time since `processed_slot` changed, or since the plugin was loaded
+    pub slot_age: Duration,
+}
+
+impl FeedHealth {
+    pub fn new(config: &ConfigHealth, processed_slot: Slot, lag: Slot, slot_age: Duration) -> Self {
+        let status = if slot_age > config.max_slot_age {
+            FeedStatus::Stale
+        } else if processed_slot == 0 {
+            FeedStatus::Starting
+        } else if config.max_slot_lag.is_some_and(|max| lag > max) {
+            FeedStatus::Behind
+        } else {
+            FeedStatus::Healthy
+        };
+        Self {
+            status,
+            processed_slot,
+            lag,
+            slot_age,
+        }
+    }
+
+    /// Drives the gRPC health service and `/ready`
+    pub fn is_serving(&self) -> bool {
+        self.status == FeedStatus::Healthy
+    }
+
+    /// Drives `/health`, a node that is behind or still starting keeps running
+    pub fn is_alive(&self) -> bool {
+        self.status != FeedStatus::Stale
+    }
+}
+
+impl fmt::Display for FeedHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status {
+            FeedStatus::Starting => write!(f, "starting, no processed slot yet"),
+            FeedStatus::Healthy => write!(
+                f,
+                "ok, processed slot {}, {} slots behind",
+                self.processed_slot, self.lag
+            ),
+            FeedStatus::Stale if self.processed_slot == 0 => write!(
+                f,
+                "stale, no processed slot {:?} after start",
+                self.slot_age
+            ),
+            FeedStatus::Stale => write!(
+                f,
+                "stale, processed slot {} received {:?} ago",
+                self.processed_slot, self.slot_age
+            ),
+            FeedStatus::Behind => write!(
+                f,
+                "behind, processed slot {} is {} slots behind",
+                self.processed_slot, self.lag
+            ),
+        }
+    }
+}
+
+/// Tracks `SLOT_STATUS` and `SLOT_STATUS_PLUGIN` and publishes `FeedHealth` every `check_interval`
+#[derive(Debug)]
+pub struct HealthMonitor {
+    config: ConfigHealth,
+    health_tx: watch::Sender<FeedHealth>,
+    loaded_at: Instant,
+}
+
+impl HealthMonitor {
+    pub fn new(config: ConfigHealth) -> Arc<Self> {
+        let (health_tx, _) = watch::channel(FeedHealth {
+            status: FeedStatus::Starting,
+            processed_slot: 0,
+            lag: 0,
+            slot_age: Duration::ZERO,
+        });
+        Arc::new(Self {
+            config,
+            health_tx,
+            loaded_at: Instant::now(),
+        })
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<FeedHealth> {
+        self.health_tx.subscribe()
+    }
+
+    pub fn health(&self) -> FeedHealth {
+        *self.health_tx.borrow()
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut ticker = interval(self.config.check_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let (mut last_slot, mut last_slot_at) = (0, self.loaded_at);
+        loop {
+            ticker.tick().await;
+
+            let processed_slot = metrics::slot_plugin_status(SlotStatus::Processed);
+            if processed_slot != last_slot {
+                (last_slot, last_slot_at) = (processed_slot, Instant::now());
+            }
+            let lag = metrics::slot_status_max().saturating_sub(processed_slot);
+
+            let health = FeedHealth::new(&self.config, processed_slot, lag, last_slot_at.elapsed());
+            if self.health_tx.send_replace(health).status != health.status {
+                if health.is_serving() {
+                    info!("geyser feed is healthy: {health}");
+                } else {
+                    warn!("geyser feed is not serving: {health}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(processed_slot: Slot, lag: Slot, slot_age: Duration) -> FeedStatus {
+        let config = ConfigHealth {
+            max_slot_age: Duration::from_secs(30),
+            max_slot_lag: Some(100),
+            check_interval: Duration::from_secs(1),
+        };
+        FeedHealth::new(&config, processed_slot, lag, slot_age).status
+    }
+
+    #[test]
+    fn test_feed_status() {
+        assert_eq!(status(0, 0, Duration::from_secs(1)), FeedStatus::Starting);
+        assert_eq!(
+            status(0, 500, Duration::from_secs(30)),
+            FeedStatus::Starting
+        );
+        // no processed slot since the plugin was loaded
+        assert_eq!(status(0, 0, Duration::from_secs(31)), FeedStatus::Stale);
+        assert_eq!(status(10, 0, Duration::from_secs(31)), FeedStatus::Stale);
+        assert_eq!(status(10, 500, Duration::from_secs(31)), FeedStatus::Stale);
+        assert_eq!(status(10, 101, Duration::from_secs(1)), FeedStatus::Behind);
+        assert_eq!(status(10, 100, Duration::from_secs(1)), FeedStatus::Healthy);
+        assert_eq!(status(10, 0, Duration::ZERO), FeedStatus::Healthy);
+    }
+
+    #[test]
+    fn test_feed_status_without_max_lag() {
+        let config = ConfigHealth::default();
+        let health = FeedHealth::new(&config, 10, 10_000, Duration::from_secs(1));
+        assert_eq!(health.status, FeedStatus::Healthy);
+        assert!(health.is_serving());
+    }
+
+    #[test]
+    fn test_liveness() {
+        let health = |processed_slot, slot_age| {
+            FeedHealth::new(&ConfigHealth::default(), processed_slot, 0, slot_age)
+        };
+        let starting = health(0, Duration::from_secs(1));
+        assert!(starting.is_alive() && !starting.is_serving());
+        let never_started = health(0, Duration::from_secs(60));
+        assert!(!never_started.is_alive() && !never_started.is_serving());
+        assert_eq!(
+            never_started.to_string(),
+            "stale, no processed slot 60s after start"
+        );
+    }
+}
@@ -1,9 +1,16 @@
+pub mod admin;
+pub mod auth;
+pub mod billing;
 pub mod config;
 pub mod grpc;
-pub mod kafka_producer_service;
+pub mod health;
 pub mod metrics;
+pub mod network;
 pub mod plugin;
+pub mod quota;
 pub mod redis;
+pub mod reload;
+pub mod tls;
 pub mod user_connection;
 pub mod version;
 
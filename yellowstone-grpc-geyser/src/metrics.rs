@@ -1,21 +1,28 @@
 use {
-    crate::{config::ConfigPrometheus, version::VERSION as VERSION_INFO},
+    crate::{
+        billing::unix_ms,
+        config::ConfigPrometheus,
+        health::{FeedHealth, HealthMonitor},
+        user_connection::connection_manager::ConnectionInfo, version::VERSION as VERSION_INFO,
+    },
     agave_geyser_plugin_interface::geyser_plugin_interface::SlotStatus as GeyserSlosStatus,
     http_body_util::{combinators::BoxBody, BodyExt, Empty as BodyEmpty, Full as BodyFull},
     hyper::{
         body::{Bytes, Incoming as BodyIncoming},
+        header::CONTENT_TYPE,
         service::service_fn,
-        Request, Response, StatusCode,
+        Request, Response, StatusCode, Uri,
     },
     hyper_util::{
         rt::tokio::{TokioExecutor, TokioIo},
         server::conn::auto::Builder as ServerBuilder,
     },
     log::{error, info},
-    prometheus::{IntCounterVec, IntGauge, IntCounter, Histogram,  HistogramOpts, IntGaugeVec, Opts, Registry, TextEncoder},
+    prometheus::{core::Collector, IntCounterVec, IntGauge, IntCounter, Histogram,  HistogramOpts, IntGaugeVec, Opts, Registry, TextEncoder},
+    serde::Serialize,
     solana_sdk::clock::Slot,
     std::{
-        collections::{hash_map::Entry as HashMapEntry, HashMap},
+        collections::HashMap,
         convert::Infallible,
         sync::{Arc, Once},
     },
@@ -80,6 +87,26 @@ lazy_static::lazy_static! {
         HistogramOpts::from(Opts::new("billing_event_send_duration_seconds", "Duration to send billing events"))
     ).unwrap();
 
+    pub static ref BILLING_SPOOL_SIZE_BYTES: IntGauge = IntGauge::new(
+        "billing_spool_size_bytes", "Size of billing events waiting in the spool"
+    ).unwrap();
+
+    pub static ref BILLING_SPOOL_OLDEST_PENDING_AGE: IntGauge = IntGauge::new(
+        "billing_spool_oldest_pending_age_seconds", "Age of the oldest spooled billing segment"
+    ).unwrap();
+
+    pub static ref BILLING_EVENTS_SPOOLED: IntCounter = IntCounter::new(
+        "billing_events_spooled_total", "Number of billing events written to the spool"
+    ).unwrap();
+
+    pub static ref BILLING_SPOOL_INVALID_EVENTS: IntCounter = IntCounter::new(
+        "billing_spool_invalid_events_total", "Number of unparsable spooled billing events, e.g. truncated by a crash"
+    ).unwrap();
+
+    pub static ref BILLING_SPOOL_FAILED_SEGMENTS: IntCounter = IntCounter::new(
+        "billing_spool_failed_segments_total", "Number of unreadable spool segments moved aside as .failed"
+    ).unwrap();
+
     pub static ref QUOTA_CHECKER_RUNS: IntCounter = IntCounter::new(
         "quota_checker_runs_total", "Number of times the quota checker loop has run"
     ).unwrap();
@@ -92,6 +119,71 @@ lazy_static::lazy_static! {
         "teams_capped_total", "Number of teams capped in quota loop"
     ).unwrap();
 
+    pub static ref QUOTA_PUSH_EVENTS: IntCounter = IntCounter::new(
+        "quota_push_events_total", "Number of cap change events received from redis"
+    ).unwrap();
+
+    pub static ref SUBSCRIBE_QUOTA_REJECTED: IntCounter = IntCounter::new(
+        "subscribe_quota_rejected_total", "Number of subscriptions rejected because the team is capped"
+    ).unwrap();
+
+    pub static ref TEAMS_HARD_LIMITED: IntCounter = IntCounter::new(
+        "teams_hard_limited_total", "Number of times a team reached its hard usage limit"
+    ).unwrap();
+
+    pub static ref TEAMS_SOFT_LIMITED: IntCounter = IntCounter::new(
+        "teams_soft_limited_total", "Number of times a team reached its soft usage limit"
+    ).unwrap();
+
+    pub static ref QUOTA_WARNINGS_SENT: IntCounter = IntCounter::new(
+        "quota_warnings_sent_total", "Number of quota warnings pushed to the streams of a team"
+    ).unwrap();
+
+    pub static ref USAGE_WRITE_ERRORS: IntCounter = IntCounter::new(
+        "usage_write_errors_total", "Number of failed writes of team usage to the quota backend"
+    ).unwrap();
+
+    pub static ref AUTH_REJECTED: IntCounter = IntCounter::new(
+        "auth_rejected_total", "Number of streams rejected for missing or invalid credentials"
+    ).unwrap();
+
+    pub static ref TEAM_STREAMS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("team_streams", "Number of concurrent streams by team"),
+        &["team_id"]
+    ).unwrap();
+
+    pub static ref STREAMS_REJECTED: IntCounterVec = IntCounterVec::new(
+        Opts::new("streams_rejected_total", "Number of streams rejected by the exceeded limit: team or app"),
+        &["limit"]
+    ).unwrap();
+
+    pub static ref THROTTLE_DELAYS: IntCounter = IntCounter::new(
+        "throttle_delays_total", "Number of sends delayed by the bandwidth limit of a team"
+    ).unwrap();
+
+    pub static ref THROTTLE_DROPPED_MESSAGES: IntCounter = IntCounter::new(
+        "throttle_dropped_messages_total", "Number of low priority messages dropped by the bandwidth limit of a team"
+    ).unwrap();
+
+    pub static ref THROTTLE_DISCONNECTS: IntCounter = IntCounter::new(
+        "throttle_disconnects_total", "Number of streams closed by the bandwidth limit of a team"
+    ).unwrap();
+
+    pub static ref REDIS_CACHE_LOOKUPS: IntCounterVec = IntCounterVec::new(
+        Opts::new("redis_cache_lookups_total", "Number of redis cache lookups by result: hit, stale, miss or fallback"),
+        &["cache", "result"]
+    ).unwrap();
+
+    pub static ref REDIS_CACHE_REFRESHES: IntCounterVec = IntCounterVec::new(
+        Opts::new("redis_cache_refreshes_total", "Number of keys fetched from redis by the cache"),
+        &["cache"]
+    ).unwrap();
+
+    pub static ref REDIS_CACHE_ERRORS: IntCounterVec = IntCounterVec::new(
+        Opts::new("redis_cache_errors_total", "Number of keys the cache failed to fetch from redis"),
+        &["cache"]
+    ).unwrap();
+
     pub static ref QUOTA_CHECKER_DURATION: Histogram = Histogram::with_opts(
         HistogramOpts::from(Opts::new("quota_checker_duration_seconds", "Quota checker loop duration"))
     ).unwrap();
@@ -99,9 +191,25 @@ lazy_static::lazy_static! {
 
 #[derive(Debug)]
 pub enum DebugClientMessage {
-    UpdateFilter { id: usize, filter: Box<Filter> },
-    UpdateSlot { id: usize, slot: Slot },
-    Removed { id: usize },
+    Connected {
+        id: usize,
+        info: Arc<ConnectionInfo>,
+        filter: Box<Filter>,
+        queue_capacity: usize,
+    },
+    UpdateFilter {
+        id: usize,
+        filter: Box<Filter>,
+    },
+    UpdateSlot {
+        id: usize,
+        slot: Slot,
+        queue_depth: usize,
+        bytes_sent: u64,
+    },
+    Removed {
+        id: usize,
+    },
 }
 
 impl DebugClientMessage {
@@ -112,15 +220,41 @@ impl DebugClientMessage {
     }
 }
 
-#[derive(Debug)]
-struct DebugClientStatus {
-    filter: Box<Filter>,
+/// Filter and progress of a client, as reported through `DebugClientMessage`
+#[derive(Debug, Clone)]
+pub struct DebugClientRecord {
+    pub id: usize,
+    pub info: Arc<ConnectionInfo>,
+    pub filter: Box<Filter>,
+    pub processed_slot: Slot,
+    /// Messages waiting in the client queue at the last processed slot
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    /// Encoded bytes sent to the client at the last processed slot
+    pub bytes_sent: u64,
+}
+
+/// JSON form of `DebugClientRecord` served on `/debug_clients/json`
+#[derive(Debug, Serialize)]
+struct DebugClientJson<'a> {
+    id: usize,
+    team_id: &'a str,
+    app_id: &'a str,
+    endpoint: &'a str,
+    network: &'a str,
+    connected_at_ms: u64,
     processed_slot: Slot,
+    /// Slots between the plugin and the client
+    lag: Slot,
+    queue_depth: usize,
+    queue_capacity: usize,
+    bytes_sent: u64,
+    filter: &'a Filter,
 }
 
 #[derive(Debug)]
-struct DebugClientStatuses {
-    requests_tx: mpsc::UnboundedSender<oneshot::Sender<String>>,
+pub struct DebugClientStatuses {
+    requests_tx: mpsc::UnboundedSender<oneshot::Sender<Vec<DebugClientRecord>>>,
     jh: JoinHandle<()>,
 }
 
@@ -131,7 +265,7 @@ impl Drop for DebugClientStatuses {
 }
 
 impl DebugClientStatuses {
-    fn new(clients_rx: mpsc::UnboundedReceiver<DebugClientMessage>) -> Arc<Self> {
+    pub fn new(clients_rx: mpsc::UnboundedReceiver<DebugClientMessage>) -> Arc<Self> {
         let (requests_tx, requests_rx) = mpsc::unbounded_channel();
         let jh = tokio::spawn(Self::run(clients_rx, requests_rx));
         Arc::new(Self { requests_tx, jh })
@@ -139,28 +273,33 @@ impl DebugClientStatuses {
 
     async fn run(
         mut clients_rx: mpsc::UnboundedReceiver<DebugClientMessage>,
-        mut requests_rx: mpsc::UnboundedReceiver<oneshot::Sender<String>>,
+        mut requests_rx: mpsc::UnboundedReceiver<oneshot::Sender<Vec<DebugClientRecord>>>,
     ) {
-        let mut clients = HashMap::<usize, DebugClientStatus>::new();
+        let mut clients = HashMap::<usize, DebugClientRecord>::new();
         loop {
             tokio::select! {
                 Some(message) = clients_rx.recv() => match message {
+                    DebugClientMessage::Connected { id, info, filter, queue_capacity } => {
+                        clients.insert(id, DebugClientRecord {
+                            id,
+                            info,
+                            filter,
+                            processed_slot: 0,
+                            queue_depth: 0,
+                            queue_capacity,
+                            bytes_sent: 0,
+                        });
+                    }
                     DebugClientMessage::UpdateFilter { id, filter } => {
-                        match clients.entry(id) {
-                            HashMapEntry::Occupied(mut entry) => {
-                                entry.get_mut().filter = filter;
-                            }
-                            HashMapEntry::Vacant(entry) => {
-                                entry.insert(DebugClientStatus {
-                                    filter,
-                                    processed_slot: 0,
-                                });
-                            }
+                        if let Some(status) = clients.get_mut(&id) {
+                            status.filter = filter;
                         }
                     }
-                    DebugClientMessage::UpdateSlot { id, slot } => {
+                    DebugClientMessage::UpdateSlot { id, slot, queue_depth, bytes_sent } => {
                         if let Some(status) = clients.get_mut(&id) {
                             status.processed_slot = slot;
+                            status.queue_depth = queue_depth;
+                            status.bytes_sent = bytes_sent;
                         }
                     }
                     DebugClientMessage::Removed { id } => {
@@ -168,28 +307,16 @@ impl DebugClientStatuses {
                     }
                 },
                 Some(tx) = requests_rx.recv() => {
-                    let mut statuses: Vec<(usize, String)> = clients.iter().map(|(id, status)| {
-                        (*id, format!("client#{id:06}, {}, {:?}", status.processed_slot, status.filter))
-                    }).collect();
-                    statuses.sort();
-
-                    let mut status = statuses.into_iter().fold(String::new(), |mut acc: String, (_id, status)| {
-                        if !acc.is_empty() {
-                            acc += "\n";
-                        }
-                        acc + &status
-                    });
-                    if !status.is_empty() {
-                        status += "\n";
-                    }
-
-                    let _ = tx.send(status);
+                    let mut records: Vec<DebugClientRecord> = clients.values().cloned().collect();
+                    records.sort_by_key(|record| record.id);
+                    let _ = tx.send(records);
                 },
             }
         }
     }
 
-    async fn get_statuses(&self) -> anyhow::Result<String> {
+    /// Records of all clients sorted by id
+    pub async fn get_records(&self) -> anyhow::Result<Vec<DebugClientRecord>> {
         let (tx, rx) = oneshot::channel();
         self.requests_tx
             .send(tx)
@@ -197,6 +324,47 @@ impl DebugClientStatuses {
         rx.await
             .map_err(|_error| anyhow::anyhow!("failed to wait response"))
     }
+
+    async fn get_statuses(&self) -> anyhow::Result<String> {
+        let records = self.get_records().await?;
+        let mut status = records.into_iter().fold(String::new(), |mut acc: String, record| {
+            if !acc.is_empty() {
+                acc += "\n";
+            }
+            acc + &format!(
+                "client#{:06}, {}, {:?}",
+                record.id, record.processed_slot, record.filter
+            )
+        });
+        if !status.is_empty() {
+            status += "\n";
+        }
+        Ok(status)
+    }
+
+    async fn get_statuses_json(&self, team_id: Option<&str>) -> anyhow::Result<String> {
+        let records = self.get_records().await?;
+        let plugin_slot = slot_plugin_status(SlotStatus::Processed);
+        let clients = records
+            .iter()
+            .filter(|record| team_id.is_none_or(|team_id| record.info.team_id == team_id))
+            .map(|record| DebugClientJson {
+                id: record.id,
+                team_id: &record.info.team_id,
+                app_id: &record.info.app_id,
+                endpoint: &record.info.endpoint,
+                network: &record.info.network,
+                connected_at_ms: unix_ms(record.info.connected_at),
+                processed_slot: record.processed_slot,
+                lag: plugin_slot.saturating_sub(record.processed_slot),
+                queue_depth: record.queue_depth,
+                queue_capacity: record.queue_capacity,
+                bytes_sent: record.bytes_sent,
+                filter: &record.filter,
+            })
+            .collect::<Vec<_>>();
+        Ok(serde_json::to_string(&clients)?)
+    }
 }
 
 #[derive(Debug)]
@@ -208,7 +376,8 @@ pub struct PrometheusService {
 impl PrometheusService {
     pub async fn new(
         config: Option<ConfigPrometheus>,
-        debug_clients_rx: Option<mpsc::UnboundedReceiver<DebugClientMessage>>,
+        debug_clients_statuses: Option<Arc<DebugClientStatuses>>,
+        health: Arc<HealthMonitor>,
     ) -> std::io::Result<Self> {
         static REGISTER: Once = Once::new();
         REGISTER.call_once(|| {
@@ -230,10 +399,30 @@ impl PrometheusService {
             register!(BILLING_EVENTS_SENT);
             register!(BILLING_EVENT_SEND_ERRORS);
             register!(BILLING_EVENT_SEND_DURATION);
+            register!(BILLING_SPOOL_SIZE_BYTES);
+            register!(BILLING_SPOOL_OLDEST_PENDING_AGE);
+            register!(BILLING_EVENTS_SPOOLED);
+            register!(BILLING_SPOOL_INVALID_EVENTS);
+            register!(BILLING_SPOOL_FAILED_SEGMENTS);
             register!(QUOTA_CHECKER_RUNS);
             register!(TEAMS_CHECKED);
             register!(TEAMS_CAPPED);
             register!(QUOTA_CHECKER_DURATION);
+            register!(SUBSCRIBE_QUOTA_REJECTED);
+            register!(TEAMS_HARD_LIMITED);
+            register!(TEAMS_SOFT_LIMITED);
+            register!(USAGE_WRITE_ERRORS);
+            register!(QUOTA_WARNINGS_SENT);
+            register!(QUOTA_PUSH_EVENTS);
+            register!(AUTH_REJECTED);
+            register!(TEAM_STREAMS);
+            register!(STREAMS_REJECTED);
+            register!(THROTTLE_DELAYS);
+            register!(THROTTLE_DROPPED_MESSAGES);
+            register!(THROTTLE_DISCONNECTS);
+            register!(REDIS_CACHE_LOOKUPS);
+            register!(REDIS_CACHE_REFRESHES);
+            register!(REDIS_CACHE_ERRORS);
 
             VERSION
                 .with_label_values(&[
@@ -249,11 +438,7 @@ impl PrometheusService {
         });
 
         let shutdown = Arc::new(Notify::new());
-        let mut debug_clients_statuses = None;
         if let Some(ConfigPrometheus { address }) = config {
-            if let Some(debug_clients_rx) = debug_clients_rx {
-                debug_clients_statuses = Some(DebugClientStatuses::new(debug_clients_rx));
-            }
             let debug_clients_statuses2 = debug_clients_statuses.clone();
 
             let shutdown = Arc::clone(&shutdown);
@@ -274,33 +459,28 @@ impl PrometheusService {
                         }
                     };
                     let debug_clients_statuses = debug_clients_statuses2.clone();
+                    let health = Arc::clone(&health);
                     tokio::spawn(async move {
                         if let Err(error) = ServerBuilder::new(TokioExecutor::new())
                             .serve_connection(
                                 TokioIo::new(stream),
                                 service_fn(move |req: Request<BodyIncoming>| {
                                     let debug_clients_statuses = debug_clients_statuses.clone();
+                                    let health = health.health();
                                     async move {
                                         match req.uri().path() {
                                             "/metrics" => metrics_handler(),
-                                            "/debug_clients" => {
+                                            "/health" => health_handler(health.is_alive(), health),
+                                            "/ready" => health_handler(health.is_serving(), health),
+                                            "/debug_clients" | "/debug_clients/json" => {
                                                 if let Some(debug_clients_statuses) =
                                                     &debug_clients_statuses
                                                 {
-                                                    let (status, body) =
-                                                        match debug_clients_statuses
-                                                            .get_statuses()
-                                                            .await
-                                                        {
-                                                            Ok(body) => (StatusCode::OK, body),
-                                                            Err(error) => (
-                                                                StatusCode::INTERNAL_SERVER_ERROR,
-                                                                error.to_string(),
-                                                            ),
-                                                        };
-                                                    Response::builder().status(status).body(
-                                                        BodyFull::new(Bytes::from(body)).boxed(),
+                                                    debug_clients_handler(
+                                                        debug_clients_statuses,
+                                                        req.uri(),
                                                     )
+                                                    .await
                                                 } else {
                                                     not_found_handler()
                                                 }
@@ -343,6 +523,50 @@ fn metrics_handler() -> http::Result<Response<BoxBody<Bytes, Infallible>>> {
         .body(BodyFull::new(Bytes::from(metrics)).boxed())
 }
 
+fn health_handler(
+    ok: bool,
+    health: FeedHealth,
+) -> http::Result<Response<BoxBody<Bytes, Infallible>>> {
+    Response::builder()
+        .status(if ok {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        })
+        .body(BodyFull::new(Bytes::from(format!("{health}\n"))).boxed())
+}
+
+/// Plain text on `/debug_clients`, JSON on `/debug_clients/json`,
+/// which accepts `team_id` to only list clients of one team
+async fn debug_clients_handler(
+    debug_clients_statuses: &DebugClientStatuses,
+    uri: &Uri,
+) -> http::Result<Response<BoxBody<Bytes, Infallible>>> {
+    let (content_type, body) = if uri.path() == "/debug_clients/json" {
+        let team_id = uri.query().and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find_map(|(key, value)| (key == "team_id").then_some(value))
+        });
+        (
+            "application/json",
+            debug_clients_statuses
+                .get_statuses_json(team_id.as_deref())
+                .await,
+        )
+    } else {
+        ("text/plain", debug_clients_statuses.get_statuses().await)
+    };
+    match body {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, content_type)
+            .body(BodyFull::new(Bytes::from(body)).boxed()),
+        Err(error) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(BodyFull::new(Bytes::from(error.to_string())).boxed()),
+    }
+}
+
 fn not_found_handler() -> http::Result<Response<BoxBody<Bytes, Infallible>>> {
     Response::builder()
         .status(StatusCode::NOT_FOUND)
@@ -361,6 +585,24 @@ pub fn update_slot_plugin_status(status: SlotStatus, slot: u64) {
         .set(slot as i64);
 }
 
+/// Latest slot with the status sent to client queues
+pub fn slot_plugin_status(status: SlotStatus) -> Slot {
+    SLOT_STATUS_PLUGIN
+        .with_label_values(&[status.as_str()])
+        .get() as Slot
+}
+
+/// Highest slot received from Geyser with any status, usually `first_shred_received`
+pub fn slot_status_max() -> Slot {
+    SLOT_STATUS
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .map(|metric| metric.get_gauge().get_value() as Slot)
+        .max()
+        .unwrap_or(0)
+}
+
 pub fn update_invalid_blocks(reason: impl AsRef<str>) {
     INVALID_FULL_BLOCKS
         .with_label_values(&[reason.as_ref()])
@@ -405,3 +647,66 @@ pub fn missed_status_message_inc(status: SlotStatus) {
         .with_label_values(&[status.as_str()])
         .inc()
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::time::{Duration, SystemTime},
+        yellowstone_grpc_proto::{
+            plugin::filter::{limits::FilterLimits, name::FilterNames},
+            prelude::SubscribeRequest,
+        },
+    };
+
+    #[tokio::test]
+    async fn test_debug_clients_team_id() {
+        let (clients_tx, clients_rx) = mpsc::unbounded_channel();
+        let debug_clients = DebugClientStatuses::new(clients_rx);
+        let mut names = FilterNames::new(64, 1024, Duration::from_secs(1));
+        for (id, team_id) in [(1, "team/a b"), (2, "team")] {
+            let filter = Filter::new(
+                &SubscribeRequest::default(),
+                &FilterLimits::default(),
+                &mut names,
+            )
+            .unwrap();
+            let info = ConnectionInfo {
+                client_id: id,
+                team_id: team_id.to_owned(),
+                app_id: "app".to_owned(),
+                endpoint: String::new(),
+                network: "SOLANA_MAINNET".to_owned(),
+                connected_at: SystemTime::now(),
+            };
+            clients_tx
+                .send(DebugClientMessage::Connected {
+                    id,
+                    info: Arc::new(info),
+                    filter: Box::new(filter),
+                    queue_capacity: 16,
+                })
+                .unwrap();
+        }
+        while debug_clients.get_records().await.unwrap().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        for (query, expected) in [
+            ("team_id=team%2Fa+b", vec![1]),
+            ("team_id=team%2Fa%20b", vec![1]),
+            ("other=1&team_id=team", vec![2]),
+            ("", vec![1, 2]),
+        ] {
+            let uri: Uri = format!("/debug_clients/json?{query}").parse().unwrap();
+            let response = debug_clients_handler(&debug_clients, &uri).await.unwrap();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let clients: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+            let ids = clients
+                .iter()
+                .map(|client| client["id"].as_u64().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, expected, "query {query}");
+        }
+    }
+}
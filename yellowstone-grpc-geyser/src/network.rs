@@ -0,0 +1,120 @@
+use {
+    crate::config::ConfigNetwork,
+    anyhow::Context,
+    log::{info, warn},
+    solana_sdk::{
+        genesis_config::{ClusterType, GenesisConfig},
+        hash::Hash,
+    },
+    std::str::FromStr,
+    tokio::task::spawn_blocking,
+};
+
+/// Genesis hash of a well-known network name
+fn known_genesis_hash(name: &str) -> Option<Hash> {
+    let cluster_type = match name {
+        "SOLANA_MAINNET" => ClusterType::MainnetBeta,
+        "SOLANA_DEVNET" => ClusterType::Devnet,
+        "SOLANA_TESTNET" => ClusterType::Testnet,
+        _ => return None,
+    };
+    cluster_type.get_genesis_hash()
+}
+
+/// Fails if the genesis hash of the ledger doesn't belong to the configured network,
+/// so a node of one cluster can't bill its traffic as another
+pub async fn verify_genesis_hash(config: &ConfigNetwork) -> anyhow::Result<()> {
+    let known = known_genesis_hash(&config.name);
+    let expected = match &config.expected_genesis_hash {
+        Some(hash) => {
+            let hash = Hash::from_str(hash).context("invalid expected_genesis_hash")?;
+            if let Some(known) = known {
+                anyhow::ensure!(
+                    hash == known,
+                    "expected_genesis_hash {hash} doesn't match {known} of {}",
+                    config.name
+                );
+            }
+            Some(hash)
+        }
+        None => known,
+    };
+
+    if config.skip_genesis_check {
+        warn!(
+            "genesis hash of network {} is not checked, skip_genesis_check is set",
+            config.name
+        );
+        return Ok(());
+    }
+    let ledger_path = config.ledger_path.clone().with_context(|| {
+        format!(
+            "ledger_path is required to check the genesis hash of network {}, set skip_genesis_check to serve it unchecked",
+            config.name
+        )
+    })?;
+    let genesis_hash = spawn_blocking(move || GenesisConfig::load(&ledger_path))
+        .await?
+        .context("failed to load genesis config")?
+        .hash();
+
+    let expected = expected.with_context(|| {
+        format!(
+            "no known genesis hash of network {}, set expected_genesis_hash",
+            config.name
+        )
+    })?;
+    anyhow::ensure!(
+        genesis_hash == expected,
+        "genesis hash {genesis_hash} of the ledger doesn't match {expected} of network {}",
+        config.name
+    );
+    info!(
+        "serving network {} with genesis hash {genesis_hash}",
+        config.name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, solana_sdk::genesis_config::GenesisConfig};
+
+    fn config(name: &str, ledger_path: Option<&std::path::Path>) -> ConfigNetwork {
+        ConfigNetwork {
+            name: name.to_owned(),
+            ledger_path: ledger_path.map(ToOwned::to_owned),
+            expected_genesis_hash: None,
+            skip_genesis_check: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_genesis_hash() {
+        let ledger = tempfile::tempdir().unwrap();
+        let genesis = GenesisConfig::default();
+        genesis.write(ledger.path()).unwrap();
+
+        let mut network = config("LOCALNET", Some(ledger.path()));
+        // no known hash of the network
+        assert!(verify_genesis_hash(&network).await.is_err());
+        network.expected_genesis_hash = Some(genesis.hash().to_string());
+        assert!(verify_genesis_hash(&network).await.is_ok());
+
+        // ledger of another network
+        let network = config("SOLANA_MAINNET", Some(ledger.path()));
+        assert!(verify_genesis_hash(&network).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verify_genesis_hash_requires_ledger() {
+        let mut network = config("SOLANA_MAINNET", None);
+        assert!(verify_genesis_hash(&network).await.is_err());
+        network.skip_genesis_check = true;
+        assert!(verify_genesis_hash(&network).await.is_ok());
+
+        let mut network = config("SOLANA_MAINNET", None);
+        network.expected_genesis_hash = Some(Hash::default().to_string());
+        assert!(verify_genesis_hash(&network).await.is_err());
+    }
+}
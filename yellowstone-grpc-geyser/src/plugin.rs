@@ -1,8 +1,9 @@
 use {
     crate::{
-        config::Config,
         grpc::GrpcService,
-        metrics::{self, PrometheusService},
+        health::HealthMonitor,
+        metrics::{self, DebugClientStatuses, PrometheusService},
+        reload::ConfigReloader,
     },
     agave_geyser_plugin_interface::geyser_plugin_interface::{
         GeyserPlugin, GeyserPluginError, ReplicaAccountInfoVersions, ReplicaBlockInfoVersions,
@@ -65,7 +66,8 @@ impl GeyserPlugin for Plugin {
     }
 
     fn on_load(&mut self, config_file: &str, is_reload: bool) -> PluginResult<()> {
-        let config = Config::load_from_file(config_file)?;
+        let (config_reloader, config) = ConfigReloader::new(config_file)?;
+        let config_reloader = Arc::new(config_reloader);
 
         // Setup logger
         solana_logger::setup_with_default(&config.log.level);
@@ -88,18 +90,30 @@ impl GeyserPlugin for Plugin {
 
         let (snapshot_channel, grpc_channel, grpc_shutdown, prometheus) =
             runtime.block_on(async move {
+                // client statuses are shared by `/debug_clients` and the admin service
                 let (debug_client_tx, debug_client_rx) = mpsc::unbounded_channel();
+                let debug_clients = (config.debug_clients_http || config.grpc.admin.is_some())
+                    .then(|| DebugClientStatuses::new(debug_client_rx));
+                let health = HealthMonitor::new(config.grpc.health);
+                tokio::spawn(Arc::clone(&health).run());
                 let (snapshot_channel, grpc_channel, grpc_shutdown) = GrpcService::create(
                     config.tokio,
                     config.grpc,
-                    config.debug_clients_http.then_some(debug_client_tx),
+                    debug_clients.is_some().then_some(debug_client_tx),
+                    debug_clients.clone(),
+                    Arc::clone(&config_reloader),
+                    Arc::clone(&health),
                     is_reload,
                 )
                 .await
                 .map_err(|error| GeyserPluginError::Custom(format!("{error:?}").into()))?;
+                if let Some(interval) = config.config_reload_interval {
+                    tokio::spawn(config_reloader.run(interval));
+                }
                 let prometheus = PrometheusService::new(
                     config.prometheus,
-                    config.debug_clients_http.then_some(debug_client_rx),
+                    debug_clients.filter(|_| config.debug_clients_http),
+                    health,
                 )
                 .await
                 .map_err(|error| GeyserPluginError::Custom(Box::new(error)))?;
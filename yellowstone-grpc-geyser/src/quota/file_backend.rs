@@ -0,0 +1,121 @@
+use {
+    crate::quota::{QuotaBackend, QuotaKey},
+    anyhow::Context,
+    log::{error, info},
+    serde::Deserialize,
+    std::{
+        collections::{HashMap, HashSet},
+        path::{Path, PathBuf},
+        time::SystemTime,
+    },
+    tokio::sync::Mutex,
+};
+
+/// Teams of a period, either a list of capped teams or usage ratios by team
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PeriodTeams {
+    Capped(HashSet<String>),
+    UsageRatios(HashMap<String, f64>),
+}
+
+impl PeriodTeams {
+    fn usage_ratio(&self, team_id: &str) -> f64 {
+        match self {
+            Self::Capped(teams) => {
+                if teams.contains(team_id) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Self::UsageRatios(ratios) => ratios.get(team_id).copied().unwrap_or(0.0),
+        }
+    }
+}
+
+/// Teams by period, e.g. `{ "2025-06": ["team-a"], "2025-07": { "team-b": 0.85 } }`
+type TeamsByPeriod = HashMap<String, PeriodTeams>;
+
+#[derive(Debug)]
+struct FileState {
+    modified: Option<SystemTime>,
+    teams: TeamsByPeriod,
+}
+
+/// Reads capped teams or usage ratios from a JSON or TOML file (by extension),
+/// the file is re-read on lookup once its modification time changes
+#[derive(Debug)]
+pub struct FileQuotaBackend {
+    path: PathBuf,
+    state: Mutex<FileState>,
+}
+
+impl FileQuotaBackend {
+    pub async fn new(path: PathBuf) -> anyhow::Result<Self> {
+        let modified = Self::modified(&path).await?;
+        let teams = Self::load(&path).await?;
+        Ok(Self {
+            path,
+            state: Mutex::new(FileState { modified, teams }),
+        })
+    }
+
+    async fn modified(path: &Path) -> anyhow::Result<Option<SystemTime>> {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .with_context(|| format!("failed to read quota file metadata {path:?}"))?;
+        Ok(metadata.modified().ok())
+    }
+
+    async fn load(path: &Path) -> anyhow::Result<TeamsByPeriod> {
+        let data = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read quota file {path:?}"))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&data).context("failed to parse quota file as toml"),
+            _ => serde_json::from_str(&data).context("failed to parse quota file as json"),
+        }
+    }
+
+    /// Reloads the file if it was modified, on failure keeps the previous content
+    async fn reload_if_changed(&self, state: &mut FileState) {
+        let modified = match Self::modified(&self.path).await {
+            Ok(modified) => modified,
+            Err(error) => {
+                error!("{error:?}");
+                return;
+            }
+        };
+        if modified.is_some() && modified == state.modified {
+            return;
+        }
+
+        match Self::load(&self.path).await {
+            Ok(teams) => {
+                info!("reloaded quota file {:?}", self.path);
+                state.teams = teams;
+                state.modified = modified;
+            }
+            Err(error) => error!("failed to reload quota file: {error:?}"),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl QuotaBackend for FileQuotaBackend {
+    async fn usage_ratio(&self, keys: &[QuotaKey]) -> HashMap<QuotaKey, anyhow::Result<f64>> {
+        let mut state = self.state.lock().await;
+        self.reload_if_changed(&mut state).await;
+
+        keys.iter()
+            .map(|key| {
+                let usage_ratio = state
+                    .teams
+                    .get(&key.year_month)
+                    .map_or(0.0, |teams| teams.usage_ratio(&key.team_id));
+                (key.clone(), Ok(usage_ratio))
+            })
+            .collect()
+    }
+}
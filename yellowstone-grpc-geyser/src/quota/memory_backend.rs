@@ -0,0 +1,90 @@
+use {
+    crate::quota::{QuotaBackend, QuotaKey, QuotaLimits, QuotaUsage},
+    std::{collections::HashMap, sync::RwLock},
+};
+
+/// Keeps usage ratios, limits and usage in memory, for local runs and tests
+#[derive(Debug, Default)]
+pub struct MemoryQuotaBackend {
+    ratios: RwLock<HashMap<QuotaKey, f64>>,
+    limits: RwLock<HashMap<String, QuotaLimits>>,
+    usage: RwLock<HashMap<QuotaKey, QuotaUsage>>,
+}
+
+impl MemoryQuotaBackend {
+    pub fn set_capped(&self, key: QuotaKey, capped: bool) {
+        self.set_usage_ratio(key, if capped { 1.0 } else { 0.0 });
+    }
+
+    pub fn set_usage_ratio(&self, key: QuotaKey, usage_ratio: f64) {
+        self.ratios.write().unwrap().insert(key, usage_ratio);
+    }
+
+    pub fn set_limits(&self, team_id: String, limits: Option<QuotaLimits>) {
+        let mut map = self.limits.write().unwrap();
+        match limits {
+            Some(limits) => map.insert(team_id, limits),
+            None => map.remove(&team_id),
+        };
+    }
+
+    pub fn usage(&self, key: &QuotaKey) -> QuotaUsage {
+        self.usage
+            .read()
+            .unwrap()
+            .get(key)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn clear(&self) {
+        self.ratios.write().unwrap().clear();
+        self.limits.write().unwrap().clear();
+        self.usage.write().unwrap().clear();
+    }
+}
+
+#[tonic::async_trait]
+impl QuotaBackend for MemoryQuotaBackend {
+    async fn usage_ratio(&self, keys: &[QuotaKey]) -> HashMap<QuotaKey, anyhow::Result<f64>> {
+        let ratios = self.ratios.read().unwrap();
+        keys.iter()
+            .map(|key| (key.clone(), Ok(ratios.get(key).copied().unwrap_or(0.0))))
+            .collect()
+    }
+
+    async fn limits(
+        &self,
+        team_ids: &[String],
+    ) -> HashMap<String, anyhow::Result<Option<QuotaLimits>>> {
+        let limits = self.limits.read().unwrap();
+        team_ids
+            .iter()
+            .map(|team_id| (team_id.clone(), Ok(limits.get(team_id).copied())))
+            .collect()
+    }
+
+    async fn load_usage(&self, keys: &[QuotaKey]) -> anyhow::Result<HashMap<QuotaKey, QuotaUsage>> {
+        let usage = self.usage.read().unwrap();
+        Ok(keys
+            .iter()
+            .filter_map(|key| usage.get(key).map(|usage| (key.clone(), *usage)))
+            .collect())
+    }
+
+    async fn add_usage(
+        &self,
+        deltas: &[(QuotaKey, QuotaUsage)],
+    ) -> anyhow::Result<HashMap<QuotaKey, QuotaUsage>> {
+        let mut usage = self.usage.write().unwrap();
+        Ok(deltas
+            .iter()
+            .map(|(key, delta)| {
+                let total = usage.entry(key.clone()).or_default();
+                total.bytes += delta.bytes;
+                total.messages += delta.messages;
+                (key.clone(), *total)
+            })
+            .collect())
+    }
+}
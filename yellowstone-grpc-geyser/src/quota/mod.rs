@@ -0,0 +1,251 @@
+pub mod file_backend;
+pub mod memory_backend;
+pub mod quota_checker;
+pub mod quota_warner;
+pub mod throttle;
+pub mod usage_meter;
+
+use {
+    crate::{
+        config::{ConfigGrpc, ConfigQuotaBackend},
+        quota::{file_backend::FileQuotaBackend, memory_backend::MemoryQuotaBackend},
+        redis::{
+            redis_pool::RedisPool, redis_quota_backend::RedisQuotaBackend,
+            refreshing_fallback_cache::RefreshingFallbackCache,
+        },
+    },
+    anyhow::Context,
+    log::error,
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, fmt, sync::Arc},
+    time::OffsetDateTime,
+};
+
+/// Team quota in a billing period (`YYYY-MM`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QuotaKey {
+    pub year_month: String,
+    pub team_id: String,
+}
+
+impl QuotaKey {
+    pub fn current(team_id: impl Into<String>) -> Self {
+        Self {
+            year_month: current_year_month(),
+            team_id: team_id.into(),
+        }
+    }
+
+    pub fn to_suffix(&self) -> String {
+        format!("{}:{}", self.year_month, self.team_id)
+    }
+
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        let mut parts = suffix.splitn(2, ':');
+        let year_month = parts.next()?.to_string();
+        let team_id = parts.next()?.to_string();
+        Some(Self {
+            year_month,
+            team_id,
+        })
+    }
+}
+
+pub fn is_capped_ratio(usage_ratio: f64) -> bool {
+    usage_ratio >= 1.0
+}
+
+/// Redis values are either `CAPPED` or the used share of the quota, e.g. `0.85`
+pub fn parse_usage_ratio(value: Option<&str>) -> f64 {
+    match value {
+        Some("CAPPED") => 1.0,
+        Some(value) => value.trim().parse().unwrap_or(0.0),
+        None => 0.0,
+    }
+}
+
+pub fn current_year_month() -> String {
+    let now = OffsetDateTime::now_utc();
+    format!("{:04}-{:02}", now.year(), now.month() as u8)
+}
+
+/// Monthly limits of a team, a team is cut off once any hard limit is reached
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaLimits {
+    #[serde(default)]
+    pub hard_bytes: Option<u64>,
+    #[serde(default)]
+    pub soft_bytes: Option<u64>,
+    #[serde(default)]
+    pub hard_messages: Option<u64>,
+    #[serde(default)]
+    pub soft_messages: Option<u64>,
+    /// Overrides `max_streams_per_team`
+    #[serde(default)]
+    pub max_streams: Option<usize>,
+    /// Overrides `max_streams_per_app`
+    #[serde(default)]
+    pub max_streams_per_app: Option<usize>,
+    /// Overrides `throttle_bytes_per_second`
+    #[serde(default)]
+    pub bytes_per_second: Option<u64>,
+    /// Overrides `throttle_burst_bytes`
+    #[serde(default)]
+    pub burst_bytes: Option<u64>,
+}
+
+impl QuotaLimits {
+    /// Limits set in `self`, missing ones taken from `other`
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            hard_bytes: self.hard_bytes.or(other.hard_bytes),
+            soft_bytes: self.soft_bytes.or(other.soft_bytes),
+            hard_messages: self.hard_messages.or(other.hard_messages),
+            soft_messages: self.soft_messages.or(other.soft_messages),
+            max_streams: self.max_streams.or(other.max_streams),
+            max_streams_per_app: self.max_streams_per_app.or(other.max_streams_per_app),
+            bytes_per_second: self.bytes_per_second.or(other.bytes_per_second),
+            burst_bytes: self.burst_bytes.or(other.burst_bytes),
+        }
+    }
+
+    pub fn is_hard_exceeded(&self, usage: QuotaUsage) -> bool {
+        Self::exceeded(self.hard_bytes, usage.bytes)
+            || Self::exceeded(self.hard_messages, usage.messages)
+    }
+
+    pub fn is_soft_exceeded(&self, usage: QuotaUsage) -> bool {
+        Self::exceeded(self.soft_bytes, usage.bytes)
+            || Self::exceeded(self.soft_messages, usage.messages)
+    }
+
+    /// Highest used share of the hard limits, `0.0` without hard limits
+    pub fn usage_ratio(&self, usage: QuotaUsage) -> f64 {
+        let ratio = |limit: Option<u64>, value: u64| match limit {
+            Some(0) => f64::INFINITY,
+            Some(limit) => value as f64 / limit as f64,
+            None => 0.0,
+        };
+        ratio(self.hard_bytes, usage.bytes).max(ratio(self.hard_messages, usage.messages))
+    }
+
+    fn exceeded(limit: Option<u64>, value: u64) -> bool {
+        limit.is_some_and(|limit| value >= limit)
+    }
+}
+
+/// Bytes and messages sent to a team
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub bytes: u64,
+    pub messages: u64,
+}
+
+impl QuotaUsage {
+    pub const fn is_zero(&self) -> bool {
+        self.bytes == 0 && self.messages == 0
+    }
+}
+
+/// Source of team caps, lookups are batched so backends can fetch many teams at once
+#[tonic::async_trait]
+pub trait QuotaBackend: fmt::Debug + Send + Sync + 'static {
+    /// Returns used share of the quota for every key, `1.0` and above means capped,
+    /// an error means the usage is unknown
+    async fn usage_ratio(&self, keys: &[QuotaKey]) -> HashMap<QuotaKey, anyhow::Result<f64>>;
+
+    /// Returns a result for every key, an error means the cap is unknown
+    async fn is_capped(&self, keys: &[QuotaKey]) -> HashMap<QuotaKey, anyhow::Result<bool>> {
+        self.usage_ratio(keys)
+            .await
+            .into_iter()
+            .map(|(key, ratio)| (key, ratio.map(is_capped_ratio)))
+            .collect()
+    }
+
+    /// Drops cached state of the keys, called when the backend pushed a change
+    async fn invalidate(&self, _keys: &[QuotaKey]) {}
+
+    /// Returns limits for every team, `None` if the team has no limits
+    async fn limits(
+        &self,
+        team_ids: &[String],
+    ) -> HashMap<String, anyhow::Result<Option<QuotaLimits>>> {
+        team_ids
+            .iter()
+            .map(|team_id| (team_id.clone(), Ok(None)))
+            .collect()
+    }
+
+    /// Returns usage stored by all plugin instances, missing keys have no usage yet
+    async fn load_usage(
+        &self,
+        _keys: &[QuotaKey],
+    ) -> anyhow::Result<HashMap<QuotaKey, QuotaUsage>> {
+        Ok(HashMap::new())
+    }
+
+    /// Adds usage deltas and returns the new totals if the backend keeps them
+    async fn add_usage(
+        &self,
+        _deltas: &[(QuotaKey, QuotaUsage)],
+    ) -> anyhow::Result<HashMap<QuotaKey, QuotaUsage>> {
+        Ok(HashMap::new())
+    }
+}
+
+/// Checks the current month quota of a single team
+pub async fn is_team_capped(backend: &dyn QuotaBackend, team_id: &str) -> anyhow::Result<bool> {
+    let key = QuotaKey::current(team_id);
+    backend
+        .is_capped(std::slice::from_ref(&key))
+        .await
+        .remove(&key)
+        .unwrap_or_else(|| Err(anyhow::anyhow!("no quota result for team {team_id}")))
+}
+
+pub async fn create_quota_backend(config: &ConfigGrpc) -> anyhow::Result<Arc<dyn QuotaBackend>> {
+    Ok(match config.quota_backend {
+        ConfigQuotaBackend::Redis => {
+            let redis_pool = Arc::new(RedisPool::new(config)?);
+            let cache = RefreshingFallbackCache::new(
+                "usage_ratio",
+                Arc::clone(&redis_pool),
+                config.redis_prefix.clone(),
+                config,
+                Arc::new(|opt| parse_usage_ratio(opt.as_deref())),
+            );
+            let limits_cache = RefreshingFallbackCache::new(
+                "limits",
+                redis_pool,
+                format!("{}:limits", config.redis_prefix),
+                config,
+                Arc::new(|opt: Option<String>| {
+                    opt.and_then(|value| match serde_json::from_str(&value) {
+                        Ok(limits) => Some(limits),
+                        Err(error) => {
+                            error!("invalid quota limits {value:?}: {error}");
+                            None
+                        }
+                    })
+                }),
+            );
+            Arc::new(RedisQuotaBackend::new(
+                cache,
+                limits_cache,
+                config.redis_prefix.clone(),
+            ))
+        }
+        ConfigQuotaBackend::Memory => Arc::new(MemoryQuotaBackend::default()),
+        ConfigQuotaBackend::File => Arc::new(
+            FileQuotaBackend::new(
+                config
+                    .quota_file_path
+                    .clone()
+                    .context("quota_file_path is required for file quota backend")?,
+            )
+            .await?,
+        ),
+    })
+}
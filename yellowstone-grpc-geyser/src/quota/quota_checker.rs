@@ -0,0 +1,246 @@
+use {
+    crate::{
+        config::QuotaFailurePolicy,
+        metrics::{QUOTA_CHECKER_DURATION, QUOTA_CHECKER_RUNS, TEAMS_CAPPED, TEAMS_CHECKED},
+        quota::{
+            current_year_month, is_capped_ratio, quota_warner::QuotaWarner, QuotaBackend, QuotaKey,
+        },
+        reload::LiveConfig,
+        user_connection::connection_manager::{ConnectionManager, ShutdownReason},
+    },
+    log::{error, info},
+    std::sync::Arc,
+    tokio::{
+        sync::watch,
+        time::{interval, interval_at, Instant},
+    },
+    tonic::Code,
+};
+
+/// Outcome of a quota check
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaCheckSummary {
+    pub checked: usize,
+    pub capped: usize,
+    pub failed: usize,
+}
+
+/// Shuts down streams of teams that used up their quota
+#[derive(Debug)]
+pub struct QuotaChecker {
+    manager: Arc<ConnectionManager>,
+    quota_backend: Arc<dyn QuotaBackend>,
+    quota_warner: Arc<QuotaWarner>,
+    /// Interval, batch size and failure policy
+    live_config: watch::Receiver<Arc<LiveConfig>>,
+}
+
+impl QuotaChecker {
+    pub const fn new(
+        manager: Arc<ConnectionManager>,
+        quota_backend: Arc<dyn QuotaBackend>,
+        quota_warner: Arc<QuotaWarner>,
+        live_config: watch::Receiver<Arc<LiveConfig>>,
+    ) -> Self {
+        Self {
+            manager,
+            quota_backend,
+            quota_warner,
+            live_config,
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut check_interval = self.live_config.borrow().quota_check_interval;
+        let mut ticker = interval(check_interval);
+
+        loop {
+            ticker.tick().await;
+            self.check(self.manager.list_active_teams()).await;
+
+            let reloaded_interval = self.live_config.borrow().quota_check_interval;
+            if reloaded_interval != check_interval {
+                check_interval = reloaded_interval;
+                ticker = interval_at(Instant::now() + check_interval, check_interval);
+            }
+        }
+    }
+
+    /// Checks the teams, all teams with streams if empty, outside of the interval,
+    /// cached usage is dropped first so the backend is asked directly
+    pub async fn check_now(&self, team_ids: Vec<String>) -> QuotaCheckSummary {
+        let teams = if team_ids.is_empty() {
+            self.manager.list_active_teams()
+        } else {
+            team_ids
+        };
+        let year_month = current_year_month();
+        let quota_keys: Vec<QuotaKey> = teams
+            .iter()
+            .map(|team_id| QuotaKey {
+                year_month: year_month.clone(),
+                team_id: team_id.clone(),
+            })
+            .collect();
+        self.quota_backend.invalidate(&quota_keys).await;
+        self.check(teams).await
+    }
+
+    async fn check(&self, teams: Vec<String>) -> QuotaCheckSummary {
+        let start = std::time::Instant::now();
+        QUOTA_CHECKER_RUNS.inc();
+
+        TEAMS_CHECKED.inc_by(teams.len() as u64);
+        let mut summary = QuotaCheckSummary {
+            checked: teams.len(),
+            ..Default::default()
+        };
+
+        let year_month = current_year_month();
+        let live_config = Arc::clone(&self.live_config.borrow());
+
+        for team_chunk in teams.chunks(live_config.quota_check_batch_size) {
+            let quota_keys: Vec<QuotaKey> = team_chunk
+                .iter()
+                .map(|team_id| QuotaKey {
+                    year_month: year_month.clone(),
+                    team_id: team_id.clone(),
+                })
+                .collect();
+
+            let results = self.quota_backend.usage_ratio(&quota_keys).await;
+
+            for (quota_key, result) in results {
+                match result {
+                    Ok(usage_ratio) if is_capped_ratio(usage_ratio) => {
+                        TEAMS_CAPPED.inc();
+                        summary.capped += 1;
+                        info!(
+                            "Team {} is capped, shutting down connection",
+                            quota_key.team_id
+                        );
+                        self.manager
+                            .shutdown_team(&quota_key.team_id, ShutdownReason::quota_exceeded());
+                    }
+                    Ok(usage_ratio) => {
+                        self.quota_warner.observe(&quota_key.team_id, usage_ratio);
+                    }
+                    Err(e) => {
+                        summary.failed += 1;
+                        error!(
+                            "Failed to check quota for team {}: {:?}",
+                            quota_key.team_id, e
+                        );
+                        if live_config.quota_unavailable_policy == QuotaFailurePolicy::FailClosed {
+                            info!(
+                                "Quota of team {} is unavailable, shutting down connection",
+                                quota_key.team_id
+                            );
+                            self.manager.shutdown_team(
+                                &quota_key.team_id,
+                                ShutdownReason::new(Code::Unavailable, "quota unavailable"),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        QUOTA_CHECKER_DURATION.observe(start.elapsed().as_secs_f64());
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            config::ConfigGrpc,
+            quota::memory_backend::MemoryQuotaBackend,
+            user_connection::{
+                connection_manager::{ConnectionInfo, StreamLimits},
+                connection_token::ConnectionToken,
+            },
+        },
+        std::time::SystemTime,
+    };
+
+    fn checker(
+        manager: &Arc<ConnectionManager>,
+        backend: &Arc<MemoryQuotaBackend>,
+    ) -> QuotaChecker {
+        let config: ConfigGrpc = serde_json::from_value(serde_json::json!({
+            "address": "127.0.0.1:0",
+            "network": { "name": "SOLANA_MAINNET" },
+            "quota_check_batch_size": 1,
+            "quota_warning_thresholds": [0.8],
+        }))
+        .unwrap();
+        let live_config = watch::channel(Arc::new(LiveConfig::new(&config))).1;
+        QuotaChecker::new(
+            Arc::clone(manager),
+            Arc::clone(backend) as Arc<dyn QuotaBackend>,
+            Arc::new(QuotaWarner::new(live_config.clone(), Arc::clone(manager))),
+            live_config,
+        )
+    }
+
+    fn connect(
+        manager: &Arc<ConnectionManager>,
+        client_id: usize,
+        team_id: &str,
+    ) -> ConnectionToken {
+        let info = ConnectionInfo {
+            client_id,
+            team_id: team_id.to_owned(),
+            app_id: "app".to_owned(),
+            endpoint: String::new(),
+            network: "SOLANA_MAINNET".to_owned(),
+            connected_at: SystemTime::now(),
+        };
+        manager
+            .register_connection(info, StreamLimits::default())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let manager = Arc::new(ConnectionManager::new());
+        let backend = Arc::new(MemoryQuotaBackend::default());
+        let checker = checker(&manager, &backend);
+        let mut capped = connect(&manager, 1, "capped");
+        let mut warned = connect(&manager, 2, "warned");
+        let mut other = connect(&manager, 3, "other");
+        backend.set_capped(QuotaKey::current("capped"), true);
+        backend.set_usage_ratio(QuotaKey::current("warned"), 0.85);
+
+        let summary = checker.check_now(vec![]).await;
+        assert_eq!(
+            summary,
+            QuotaCheckSummary {
+                checked: 3,
+                capped: 1,
+                failed: 0,
+            }
+        );
+        assert!(capped.shutdown_rx().borrow().is_shutdown());
+        assert!(!warned.shutdown_rx().borrow().is_shutdown());
+        assert_eq!(
+            manager
+                .quota_warning("warned")
+                .map(|warning| warning.threshold),
+            Some(0.8)
+        );
+        assert!(!other.shutdown_rx().borrow().is_shutdown());
+        assert!(manager.quota_warning("other").is_none());
+
+        // only the requested teams are checked
+        backend.clear();
+        backend.set_capped(QuotaKey::current("other"), true);
+        let summary = checker.check_now(vec!["warned".to_owned()]).await;
+        assert_eq!(summary.checked, 1);
+        assert_eq!(summary.capped, 0);
+        assert!(!other.shutdown_rx().borrow().is_shutdown());
+    }
+}
@@ -0,0 +1,72 @@
+use {
+    crate::{
+        metrics::QUOTA_WARNINGS_SENT,
+        quota::current_year_month,
+        reload::LiveConfig,
+        user_connection::connection_manager::{ConnectionManager, QuotaWarning},
+    },
+    log::info,
+    std::sync::{Arc, Mutex},
+    tokio::sync::watch,
+};
+
+/// Pushes a warning to the streams of a team once per threshold and period,
+/// e.g. at 80% and 95% of the quota, before the team is cut off at 100%
+#[derive(Debug)]
+pub struct QuotaWarner {
+    live_config: watch::Receiver<Arc<LiveConfig>>,
+    manager: Arc<ConnectionManager>,
+    year_month: Mutex<String>,
+}
+
+impl QuotaWarner {
+    pub fn new(
+        live_config: watch::Receiver<Arc<LiveConfig>>,
+        manager: Arc<ConnectionManager>,
+    ) -> Self {
+        Self {
+            live_config,
+            manager,
+            year_month: Mutex::new(current_year_month()),
+        }
+    }
+
+    /// Warnings are only raised, a lower ratio from another source doesn't reset them
+    pub fn observe(&self, team_id: &str, usage_ratio: f64) {
+        {
+            let year_month = current_year_month();
+            let mut current = self.year_month.lock().unwrap();
+            if *current != year_month {
+                *current = year_month;
+                self.manager.clear_quota_warnings();
+            }
+        }
+
+        let Some(threshold) = self
+            .live_config
+            .borrow()
+            .quota_warning_thresholds
+            .iter()
+            .rev()
+            .find(|threshold| usage_ratio >= **threshold)
+            .copied()
+        else {
+            return;
+        };
+        let warned = self
+            .manager
+            .quota_warning(team_id)
+            .is_some_and(|warning| warning.threshold >= threshold);
+        if !warned {
+            QUOTA_WARNINGS_SENT.inc();
+            info!("team {team_id} used {usage_ratio:.3} of the quota, warning at {threshold}");
+            self.manager.warn_team(
+                team_id,
+                QuotaWarning {
+                    usage_ratio,
+                    threshold,
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use {
+    crate::{
+        config::ConfigThrottlePolicy,
+        metrics::{THROTTLE_DELAYS, THROTTLE_DISCONNECTS, THROTTLE_DROPPED_MESSAGES},
+    },
+    dashmap::DashMap,
+    std::{
+        collections::{HashSet, VecDeque},
+        sync::{Arc, Mutex, Weak},
+        time::{Duration, Instant},
+    },
+};
+
+/// Longest delay of a single message with the `delay` policy, the delayed queue
+/// of a stream fills up well before that if the team stays over its rate
+pub const MAX_THROTTLE_DELAY: Duration = Duration::from_secs(60);
+
+/// Bytes per second and burst size of a token bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThrottleRate {
+    pub bytes_per_second: u64,
+    pub burst_bytes: u64,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    rate: ThrottleRate,
+    /// Negative while sends are delayed, see `TokenBucket::take`
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Bandwidth shared by all streams of a team
+#[derive(Debug)]
+pub struct TokenBucket {
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    fn new(rate: ThrottleRate) -> Self {
+        Self {
+            state: Mutex::new(BucketState {
+                rate,
+                tokens: rate.burst_bytes as f64,
+                updated_at: Instant::now(),
+            }),
+        }
+    }
+
+    fn refill(state: &mut BucketState) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.updated_at).as_secs_f64();
+        state.updated_at = now;
+        state.tokens = (state.tokens + elapsed * state.rate.bytes_per_second as f64)
+            .min(state.rate.burst_bytes as f64);
+    }
+
+    fn set_rate(&self, rate: ThrottleRate) {
+        let mut state = self.state.lock().unwrap();
+        if state.rate != rate {
+            Self::refill(&mut state);
+            state.rate = rate;
+            state.tokens = state.tokens.min(rate.burst_bytes as f64);
+        }
+    }
+
+    /// Takes `bytes` only if enough tokens are left, a message larger than the burst
+    /// is let through once the bucket is full
+    pub fn try_take(&self, bytes: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        Self::refill(&mut state);
+        if state.tokens >= (bytes as f64).min(state.rate.burst_bytes as f64) {
+            state.tokens -= bytes as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes `bytes` even if it leaves the bucket in debt, returns how long
+    /// the caller has to wait until the debt is paid off, at most `MAX_THROTTLE_DELAY`
+    pub fn take(&self, bytes: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        Self::refill(&mut state);
+        state.tokens -= bytes as f64;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else if state.rate.bytes_per_second == 0 {
+            MAX_THROTTLE_DELAY
+        } else {
+            Duration::from_secs_f64(-state.tokens / state.rate.bytes_per_second as f64)
+                .min(MAX_THROTTLE_DELAY)
+        }
+    }
+}
+
+/// What to do with a message of a throttled stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleDecision {
+    Send,
+    /// Send once the delay has passed, see `DelayedMessages`
+    Delay(Duration),
+    Drop,
+    Disconnect,
+}
+
+/// Token buckets by team, a bucket lives while any stream of the team uses it
+#[derive(Debug)]
+pub struct TeamThrottles {
+    buckets: DashMap<String, Weak<TokenBucket>>,
+    policy: ConfigThrottlePolicy,
+    low_priority: HashSet<String>,
+}
+
+impl TeamThrottles {
+    pub fn new(
+        policy: ConfigThrottlePolicy,
+        low_priority: impl IntoIterator<Item = String>,
+    ) -> Self {
+        Self {
+            buckets: DashMap::new(),
+            policy,
+            low_priority: low_priority.into_iter().collect(),
+        }
+    }
+
+    /// Returns the throttle for a new stream of the team, the bucket is shared with
+    /// existing streams and takes the latest rate
+    pub fn throttle(self: &Arc<Self>, team_id: &str, rate: ThrottleRate) -> ClientThrottle {
+        let mut entry = self.buckets.entry(team_id.to_owned()).or_default();
+        let bucket = match entry.upgrade() {
+            Some(bucket) => {
+                bucket.set_rate(rate);
+                bucket
+            }
+            None => {
+                let bucket = Arc::new(TokenBucket::new(rate));
+                *entry = Arc::downgrade(&bucket);
+                bucket
+            }
+        };
+        ClientThrottle {
+            team_id: team_id.to_owned(),
+            bucket,
+            throttles: Arc::clone(self),
+        }
+    }
+}
+
+/// Meters the messages of a single stream against the bucket of its team
+#[derive(Debug)]
+pub struct ClientThrottle {
+    team_id: String,
+    bucket: Arc<TokenBucket>,
+    throttles: Arc<TeamThrottles>,
+}
+
+impl ClientThrottle {
+    /// Decides by the policy whether a message of `size` encoded bytes is sent,
+    /// with the `delay` policy the message waits until the bucket has enough tokens
+    pub fn admit(&self, message_type: &str, size: u64) -> ThrottleDecision {
+        match self.throttles.policy {
+            ConfigThrottlePolicy::Delay => {
+                let delay = self.bucket.take(size);
+                if delay.is_zero() {
+                    ThrottleDecision::Send
+                } else {
+                    THROTTLE_DELAYS.inc();
+                    ThrottleDecision::Delay(delay)
+                }
+            }
+            ConfigThrottlePolicy::DropLowPriority => {
+                if !self.throttles.low_priority.contains(message_type) {
+                    // high priority messages are always sent, but still use up bandwidth
+                    let _ = self.bucket.take(size);
+                    ThrottleDecision::Send
+                } else if self.bucket.try_take(size) {
+                    ThrottleDecision::Send
+                } else {
+                    THROTTLE_DROPPED_MESSAGES.inc();
+                    ThrottleDecision::Drop
+                }
+            }
+            ConfigThrottlePolicy::Disconnect => {
+                if self.bucket.try_take(size) {
+                    ThrottleDecision::Send
+                } else {
+                    THROTTLE_DISCONNECTS.inc();
+                    ThrottleDecision::Disconnect
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ClientThrottle {
+    fn drop(&mut self) {
+        // the entry lock is held while checking, so no other stream can pick the bucket up
+        self.throttles
+            .buckets
+            .remove_if(&self.team_id, |_, _| Arc::strong_count(&self.bucket) == 1);
+    }
+}
+
+/// Messages of a stream held back by the `delay` policy, released in order once
+/// their delay has passed, so the stream keeps reading broadcasts and shutdown
+/// signals while it's slowed down
+#[derive(Debug)]
+pub struct DelayedMessages<T> {
+    queue: VecDeque<(tokio::time::Instant, T)>,
+    capacity: usize,
+}
+
+impl<T> DelayedMessages<T> {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Queues a message behind the ones already delayed, returns it back
+    /// if the queue is full
+    pub fn push(&mut self, delay: Duration, message: T) -> Result<(), T> {
+        if self.queue.len() >= self.capacity {
+            return Err(message);
+        }
+        let mut ready_at = tokio::time::Instant::now() + delay;
+        if let Some((last, _)) = self.queue.back() {
+            ready_at = ready_at.max(*last);
+        }
+        self.queue.push_back((ready_at, message));
+        Ok(())
+    }
+
+    /// When the next message can be sent
+    pub fn ready_at(&self) -> Option<tokio::time::Instant> {
+        self.queue.front().map(|(ready_at, _)| *ready_at)
+    }
+
+    pub fn pop_ready(&mut self, now: tokio::time::Instant) -> Option<T> {
+        if self.ready_at()? <= now {
+            self.queue.pop_front().map(|(_, message)| message)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttles(policy: ConfigThrottlePolicy) -> Arc<TeamThrottles> {
+        Arc::new(TeamThrottles::new(policy, ["account".to_owned()]))
+    }
+
+    const RATE: ThrottleRate = ThrottleRate {
+        bytes_per_second: 1_000,
+        burst_bytes: 1_000,
+    };
+
+    #[test]
+    fn test_delay() {
+        let throttle = throttles(ConfigThrottlePolicy::Delay).throttle("team", RATE);
+        assert_eq!(throttle.admit("account", 1_000), ThrottleDecision::Send);
+        let ThrottleDecision::Delay(delay) = throttle.admit("account", 500) else {
+            panic!("expected delay");
+        };
+        assert!(delay > Duration::from_millis(400) && delay <= Duration::from_millis(500));
+
+        // capped for a large debt and a zero rate
+        let ThrottleDecision::Delay(delay) = throttle.admit("account", u32::MAX as u64) else {
+            panic!("expected delay");
+        };
+        assert_eq!(delay, MAX_THROTTLE_DELAY);
+        let rate = ThrottleRate {
+            bytes_per_second: 0,
+            burst_bytes: 0,
+        };
+        let throttle = throttles(ConfigThrottlePolicy::Delay).throttle("team", rate);
+        assert_eq!(
+            throttle.admit("account", 1),
+            ThrottleDecision::Delay(MAX_THROTTLE_DELAY)
+        );
+    }
+
+    #[test]
+    fn test_drop_low_priority() {
+        let throttle = throttles(ConfigThrottlePolicy::DropLowPriority).throttle("team", RATE);
+        assert_eq!(throttle.admit("account", 800), ThrottleDecision::Send);
+        assert_eq!(throttle.admit("account", 800), ThrottleDecision::Drop);
+        // high priority messages are sent and keep the bucket empty
+        assert_eq!(throttle.admit("slot", 800), ThrottleDecision::Send);
+        assert_eq!(throttle.admit("account", 100), ThrottleDecision::Drop);
+    }
+
+    #[test]
+    fn test_disconnect_shared_bucket() {
+        let throttles = throttles(ConfigThrottlePolicy::Disconnect);
+        let throttle1 = throttles.throttle("team", RATE);
+        let throttle2 = throttles.throttle("team", RATE);
+        assert_eq!(throttle1.admit("slot", 800), ThrottleDecision::Send);
+        assert_eq!(throttle2.admit("slot", 800), ThrottleDecision::Disconnect);
+        // other teams have their own bucket
+        let other = throttles.throttle("other", RATE);
+        assert_eq!(other.admit("slot", 800), ThrottleDecision::Send);
+
+        drop(throttle1);
+        assert!(throttles.buckets.contains_key("team"));
+        drop(throttle2);
+        assert!(!throttles.buckets.contains_key("team"));
+    }
+
+    #[test]
+    fn test_delayed_messages() {
+        let mut delayed = DelayedMessages::new(2);
+        assert!(delayed.is_empty());
+        assert_eq!(delayed.ready_at(), None);
+
+        delayed.push(Duration::from_secs(10), 1).unwrap();
+        // sent after the message delayed before it
+        delayed.push(Duration::ZERO, 2).unwrap();
+        assert_eq!(delayed.push(Duration::ZERO, 3), Err(3));
+
+        let now = tokio::time::Instant::now();
+        assert_eq!(delayed.pop_ready(now), None);
+        let later = now + Duration::from_secs(11);
+        assert_eq!(delayed.pop_ready(later), Some(1));
+        assert_eq!(delayed.pop_ready(later), Some(2));
+        assert_eq!(delayed.pop_ready(later), None);
+        assert!(delayed.is_empty());
+    }
+}
@@ -0,0 +1,466 @@
+use {
+    crate::{
+        metrics::{TEAMS_HARD_LIMITED, TEAMS_SOFT_LIMITED, USAGE_WRITE_ERRORS},
+        quota::{
+            current_year_month, quota_warner::QuotaWarner, QuotaBackend, QuotaKey, QuotaLimits,
+            QuotaUsage,
+        },
+        user_connection::connection_manager::{ConnectionManager, ShutdownReason},
+    },
+    dashmap::DashMap,
+    log::{error, info, warn},
+    std::sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    tokio::time::{interval, Duration, MissedTickBehavior},
+    tonic::Code,
+};
+
+/// Usage of a single team in the current month, shared by all connections of the team
+#[derive(Debug, Default)]
+pub struct TeamUsage {
+    /// Recorded locally and not written to the backend yet
+    pending_bytes: AtomicU64,
+    pending_messages: AtomicU64,
+    /// Total known to the backend, includes usage of other plugin instances
+    stored_bytes: AtomicU64,
+    stored_messages: AtomicU64,
+    seeded: AtomicBool,
+    limits: Mutex<Option<QuotaLimits>>,
+    hard_limited: AtomicBool,
+    soft_limited: AtomicBool,
+}
+
+impl TeamUsage {
+    pub fn record(&self, bytes: u64, messages: u64) {
+        self.pending_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.pending_messages.fetch_add(messages, Ordering::Relaxed);
+    }
+
+    pub fn total(&self) -> QuotaUsage {
+        let stored = self.stored();
+        let pending = self.pending();
+        QuotaUsage {
+            bytes: stored.bytes + pending.bytes,
+            messages: stored.messages + pending.messages,
+        }
+    }
+
+    pub fn limits(&self) -> Option<QuotaLimits> {
+        *self.limits.lock().unwrap()
+    }
+
+    pub fn is_hard_limited(&self) -> bool {
+        self.hard_limited.load(Ordering::Relaxed)
+    }
+
+    fn pending(&self) -> QuotaUsage {
+        QuotaUsage {
+            bytes: self.pending_bytes.load(Ordering::Relaxed),
+            messages: self.pending_messages.load(Ordering::Relaxed),
+        }
+    }
+
+    fn stored(&self) -> QuotaUsage {
+        QuotaUsage {
+            bytes: self.stored_bytes.load(Ordering::Relaxed),
+            messages: self.stored_messages.load(Ordering::Relaxed),
+        }
+    }
+
+    fn take_pending(&self) -> QuotaUsage {
+        QuotaUsage {
+            bytes: self.pending_bytes.swap(0, Ordering::Relaxed),
+            messages: self.pending_messages.swap(0, Ordering::Relaxed),
+        }
+    }
+
+    fn set_stored(&self, usage: QuotaUsage) {
+        self.stored_bytes.store(usage.bytes, Ordering::Relaxed);
+        self.stored_messages
+            .store(usage.messages, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        self.set_stored(QuotaUsage::default());
+        self.seeded.store(false, Ordering::Relaxed);
+        self.hard_limited.store(false, Ordering::Relaxed);
+        self.soft_limited.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Counts bytes and messages sent to every team in the current month and cuts off
+/// teams as soon as they reach their hard limit, without waiting for the billing pipeline.
+/// Counters are seeded from the quota backend and deltas are written back periodically.
+#[derive(Debug)]
+pub struct UsageMeter {
+    teams: DashMap<String, Arc<TeamUsage>>,
+    year_month: Mutex<String>,
+}
+
+impl Default for UsageMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UsageMeter {
+    pub fn new() -> Self {
+        Self {
+            teams: DashMap::new(),
+            year_month: Mutex::new(current_year_month()),
+        }
+    }
+
+    pub fn team(&self, team_id: &str) -> Arc<TeamUsage> {
+        if let Some(usage) = self.teams.get(team_id) {
+            return Arc::clone(&usage);
+        }
+        Arc::clone(&self.teams.entry(team_id.to_owned()).or_default())
+    }
+
+    pub fn is_hard_limited(&self, team_id: &str) -> bool {
+        self.teams
+            .get(team_id)
+            .is_some_and(|usage| usage.is_hard_limited())
+    }
+
+    fn snapshot(&self) -> Vec<(String, Arc<TeamUsage>)> {
+        self.teams
+            .iter()
+            .map(|entry| (entry.key().clone(), Arc::clone(entry.value())))
+            .collect()
+    }
+
+    fn key(&self, team_id: &str) -> QuotaKey {
+        QuotaKey {
+            year_month: self.year_month.lock().unwrap().clone(),
+            team_id: team_id.to_owned(),
+        }
+    }
+
+    /// Loads stored usage of teams seen for the first time this month
+    async fn seed(&self, backend: &dyn QuotaBackend) {
+        let teams: Vec<_> = self
+            .snapshot()
+            .into_iter()
+            .filter(|(_, usage)| !usage.seeded.load(Ordering::Relaxed))
+            .collect();
+        if teams.is_empty() {
+            return;
+        }
+
+        let keys: Vec<QuotaKey> = teams.iter().map(|(team_id, _)| self.key(team_id)).collect();
+        match backend.load_usage(&keys).await {
+            Ok(mut stored) => {
+                for ((_, usage), key) in teams.iter().zip(keys) {
+                    usage.set_stored(stored.remove(&key).unwrap_or_default());
+                    usage.seeded.store(true, Ordering::Relaxed);
+                }
+            }
+            Err(error) => error!("failed to load usage of {} teams: {error:?}", keys.len()),
+        }
+    }
+
+    /// Refreshes limits and shuts down teams which reached a hard limit
+    async fn check_limits(
+        &self,
+        backend: &dyn QuotaBackend,
+        manager: &ConnectionManager,
+        warner: &QuotaWarner,
+    ) {
+        let teams = self.snapshot();
+        if teams.is_empty() {
+            return;
+        }
+
+        let team_ids: Vec<String> = teams.iter().map(|(team_id, _)| team_id.clone()).collect();
+        let mut limits = backend.limits(&team_ids).await;
+        for (team_id, usage) in teams {
+            match limits.remove(&team_id) {
+                Some(Ok(team_limits)) => *usage.limits.lock().unwrap() = team_limits,
+                Some(Err(error)) => {
+                    error!("failed to fetch limits for team {team_id}: {error:?}")
+                }
+                None => {}
+            }
+            let Some(team_limits) = usage.limits() else {
+                usage.hard_limited.store(false, Ordering::Relaxed);
+                usage.soft_limited.store(false, Ordering::Relaxed);
+                continue;
+            };
+
+            let total = usage.total();
+            warner.observe(&team_id, team_limits.usage_ratio(total));
+
+            let soft = team_limits.is_soft_exceeded(total);
+            if soft && !usage.soft_limited.swap(true, Ordering::Relaxed) {
+                TEAMS_SOFT_LIMITED.inc();
+                warn!("team {team_id} reached soft limit: {total:?} of {team_limits:?}");
+            } else if !soft {
+                usage.soft_limited.store(false, Ordering::Relaxed);
+            }
+
+            let hard = team_limits.is_hard_exceeded(total);
+            if hard {
+                if !usage.hard_limited.swap(true, Ordering::Relaxed) {
+                    TEAMS_HARD_LIMITED.inc();
+                    info!("team {team_id} reached hard limit: {total:?} of {team_limits:?}");
+                }
+                manager.shutdown_team(
+                    &team_id,
+                    ShutdownReason::new(Code::ResourceExhausted, "usage limit exceeded"),
+                );
+            } else {
+                usage.hard_limited.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Writes pending usage to the backend, on failure it's kept for the next flush
+    async fn flush(&self, backend: &dyn QuotaBackend) {
+        self.write_pending(backend).await;
+
+        // forget teams without connections, limited ones are kept to reject new streams
+        self.teams.retain(|_, usage| {
+            Arc::strong_count(usage) > 1 || usage.is_hard_limited() || !usage.pending().is_zero()
+        });
+    }
+
+    async fn write_pending(&self, backend: &dyn QuotaBackend) {
+        let teams: Vec<_> = self
+            .snapshot()
+            .into_iter()
+            .map(|(team_id, usage)| {
+                let pending = usage.take_pending();
+                (team_id, usage, pending)
+            })
+            .filter(|(_, _, pending)| !pending.is_zero())
+            .collect();
+
+        if teams.is_empty() {
+            return;
+        }
+
+        let deltas: Vec<(QuotaKey, QuotaUsage)> = teams
+            .iter()
+            .map(|(team_id, _, pending)| (self.key(team_id), *pending))
+            .collect();
+        match backend.add_usage(&deltas).await {
+            Ok(mut totals) => {
+                for ((_, usage, pending), (key, _)) in teams.iter().zip(&deltas) {
+                    let total = totals.remove(key).unwrap_or_else(|| {
+                        let stored = usage.stored();
+                        QuotaUsage {
+                            bytes: stored.bytes + pending.bytes,
+                            messages: stored.messages + pending.messages,
+                        }
+                    });
+                    usage.set_stored(total);
+                }
+            }
+            Err(error) => {
+                USAGE_WRITE_ERRORS.inc();
+                error!("failed to write usage of {} teams: {error:?}", deltas.len());
+                for (_, usage, pending) in &teams {
+                    usage.record(pending.bytes, pending.messages);
+                }
+            }
+        }
+    }
+
+    /// Writes the previous month usage and starts counting from zero
+    async fn rollover(&self, backend: &dyn QuotaBackend) {
+        let year_month = current_year_month();
+        if *self.year_month.lock().unwrap() == year_month {
+            return;
+        }
+
+        self.flush(backend).await;
+        info!("usage meter switched to {year_month}");
+        *self.year_month.lock().unwrap() = year_month;
+        for entry in self.teams.iter() {
+            entry.value().reset();
+        }
+    }
+
+    pub async fn run(
+        self: Arc<Self>,
+        backend: Arc<dyn QuotaBackend>,
+        manager: Arc<ConnectionManager>,
+        warner: Arc<QuotaWarner>,
+        check_interval: Duration,
+        flush_interval: Duration,
+    ) {
+        let mut check_ticker = interval(check_interval);
+        check_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut flush_ticker = interval(flush_interval);
+        flush_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        flush_ticker.reset();
+
+        loop {
+            tokio::select! {
+                _ = check_ticker.tick() => {
+                    self.rollover(backend.as_ref()).await;
+                    self.seed(backend.as_ref()).await;
+                    self.check_limits(backend.as_ref(), &manager, &warner).await;
+                }
+                _ = flush_ticker.tick() => {
+                    self.flush(backend.as_ref()).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            config::ConfigGrpc,
+            quota::memory_backend::MemoryQuotaBackend,
+            reload::LiveConfig,
+            user_connection::connection_manager::{ConnectionInfo, StreamLimits},
+        },
+        std::time::SystemTime,
+        tokio::sync::watch,
+    };
+
+    fn warner(manager: &Arc<ConnectionManager>) -> QuotaWarner {
+        let config: ConfigGrpc = serde_json::from_value(serde_json::json!({
+            "address": "127.0.0.1:0",
+            "network": { "name": "SOLANA_MAINNET" },
+        }))
+        .unwrap();
+        let live_config = watch::channel(Arc::new(LiveConfig::new(&config))).1;
+        QuotaWarner::new(live_config, Arc::clone(manager))
+    }
+
+    const fn usage(bytes: u64, messages: u64) -> QuotaUsage {
+        QuotaUsage { bytes, messages }
+    }
+
+    #[tokio::test]
+    async fn test_seed() {
+        let backend = MemoryQuotaBackend::default();
+        backend
+            .add_usage(&[(QuotaKey::current("team"), usage(100, 2))])
+            .await
+            .unwrap();
+
+        let meter = UsageMeter::new();
+        let team = meter.team("team");
+        team.record(10, 1);
+        meter.seed(&backend).await;
+        assert_eq!(team.total(), usage(110, 3));
+
+        // seeded once, later usage comes from flushes
+        backend
+            .add_usage(&[(QuotaKey::current("team"), usage(100, 2))])
+            .await
+            .unwrap();
+        meter.seed(&backend).await;
+        assert_eq!(team.total(), usage(110, 3));
+    }
+
+    #[tokio::test]
+    async fn test_flush() {
+        let backend = MemoryQuotaBackend::default();
+        let meter = UsageMeter::new();
+        let team = meter.team("team");
+        team.record(10, 1);
+        meter.team("idle").record(20, 2);
+
+        // another instance wrote usage of the same team
+        backend
+            .add_usage(&[(QuotaKey::current("team"), usage(100, 2))])
+            .await
+            .unwrap();
+        meter.flush(&backend).await;
+        assert_eq!(backend.usage(&QuotaKey::current("team")), usage(110, 3));
+        assert_eq!(backend.usage(&QuotaKey::current("idle")), usage(20, 2));
+        assert_eq!(team.total(), usage(110, 3));
+        assert!(team.pending().is_zero());
+
+        // teams without connections and pending usage are dropped
+        assert!(meter.teams.contains_key("team"));
+        assert!(!meter.teams.contains_key("idle"));
+        drop(team);
+        meter.flush(&backend).await;
+        assert!(meter.teams.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rollover() {
+        let backend = MemoryQuotaBackend::default();
+        let meter = UsageMeter::new();
+        *meter.year_month.lock().unwrap() = "2000-01".to_owned();
+        let team = meter.team("team");
+        team.record(10, 1);
+
+        meter.rollover(&backend).await;
+        let previous = QuotaKey {
+            year_month: "2000-01".to_owned(),
+            team_id: "team".to_owned(),
+        };
+        assert_eq!(backend.usage(&previous), usage(10, 1));
+        assert_eq!(*meter.year_month.lock().unwrap(), current_year_month());
+        assert!(team.total().is_zero());
+
+        team.record(5, 1);
+        meter.flush(&backend).await;
+        assert_eq!(backend.usage(&QuotaKey::current("team")), usage(5, 1));
+        assert_eq!(backend.usage(&previous), usage(10, 1));
+    }
+
+    #[tokio::test]
+    async fn test_hard_limit() {
+        let backend = MemoryQuotaBackend::default();
+        backend.set_limits(
+            "team".to_owned(),
+            Some(QuotaLimits {
+                hard_bytes: Some(100),
+                ..Default::default()
+            }),
+        );
+        let manager = Arc::new(ConnectionManager::new());
+        let warner = warner(&manager);
+        let mut token = manager
+            .register_connection(
+                ConnectionInfo {
+                    client_id: 1,
+                    team_id: "team".to_owned(),
+                    app_id: "app".to_owned(),
+                    endpoint: String::new(),
+                    network: "SOLANA_MAINNET".to_owned(),
+                    connected_at: SystemTime::now(),
+                },
+                StreamLimits::default(),
+            )
+            .unwrap();
+
+        let meter = UsageMeter::new();
+        let team = meter.team("team");
+        team.record(99, 1);
+        meter.check_limits(&backend, &manager, &warner).await;
+        assert!(!meter.is_hard_limited("team"));
+        assert!(!token.shutdown_rx().borrow().is_shutdown());
+
+        team.record(1, 1);
+        meter.check_limits(&backend, &manager, &warner).await;
+        assert!(meter.is_hard_limited("team"));
+        assert!(token.shutdown_rx().borrow().is_shutdown());
+
+        // limited teams are kept without connections to reject new streams
+        drop(team);
+        meter.flush(&backend).await;
+        assert!(meter.is_hard_limited("team"));
+
+        // the cut-off is lifted once the team has no limits
+        backend.set_limits("team".to_owned(), None);
+        meter.check_limits(&backend, &manager, &warner).await;
+        assert!(!meter.is_hard_limited("team"));
+    }
+}
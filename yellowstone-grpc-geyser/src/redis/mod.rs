@@ -1,2 +1,4 @@
-pub mod redis_quota_checker;
+pub mod redis_pool;
+pub mod redis_quota_backend;
+pub mod redis_quota_subscriber;
 pub mod refreshing_fallback_cache;
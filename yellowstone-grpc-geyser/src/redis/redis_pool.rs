@@ -0,0 +1,140 @@
+use {
+    crate::config::ConfigGrpc,
+    anyhow::Context,
+    deadpool_redis::{
+        cluster, redis,
+        redis::{
+            aio::ConnectionLike, cluster_routing::get_slot, Cmd, Pipeline, RedisFuture, Value,
+        },
+        sentinel, Runtime,
+    },
+    std::{collections::BTreeMap, fmt},
+};
+
+/// Connection pool to a single node, a Sentinel master set or a Cluster
+pub enum RedisPool {
+    Single(deadpool_redis::Pool),
+    Sentinel(sentinel::Pool),
+    Cluster(cluster::Pool),
+}
+
+impl fmt::Debug for RedisPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Single(_) => "Single",
+            Self::Sentinel(_) => "Sentinel",
+            Self::Cluster(_) => "Cluster",
+        };
+        f.debug_tuple("RedisPool").field(&kind).finish()
+    }
+}
+
+impl RedisPool {
+    /// Exactly one of `redis_url`, `redis_sentinel` and `redis_cluster_urls` should be set
+    pub fn new(config: &ConfigGrpc) -> anyhow::Result<Self> {
+        let configured = [
+            config.redis_url.is_some(),
+            config.redis_sentinel.is_some(),
+            !config.redis_cluster_urls.is_empty(),
+        ];
+        anyhow::ensure!(
+            configured.iter().filter(|set| **set).count() <= 1,
+            "only one of redis_url, redis_sentinel and redis_cluster_urls can be set"
+        );
+
+        if let Some(sentinel) = &config.redis_sentinel {
+            let pool = sentinel::Config::from_urls(
+                sentinel.urls.clone(),
+                sentinel.master_name.clone(),
+                sentinel::SentinelServerType::Master,
+            )
+            .create_pool(Some(Runtime::Tokio1))
+            .context("failed to create redis sentinel pool")?;
+            Ok(Self::Sentinel(pool))
+        } else if !config.redis_cluster_urls.is_empty() {
+            let pool = cluster::Config::from_urls(config.redis_cluster_urls.clone())
+                .create_pool(Some(Runtime::Tokio1))
+                .context("failed to create redis cluster pool")?;
+            Ok(Self::Cluster(pool))
+        } else {
+            let url = config
+                .redis_url
+                .as_deref()
+                .context("one of redis_url, redis_sentinel or redis_cluster_urls is required")?;
+            let pool = deadpool_redis::Config::from_url(url)
+                .create_pool(Some(Runtime::Tokio1))
+                .context("failed to create redis pool")?;
+            Ok(Self::Single(pool))
+        }
+    }
+
+    pub async fn get(&self) -> anyhow::Result<RedisConnection> {
+        Ok(match self {
+            Self::Single(pool) => RedisConnection::Single(pool.get().await?),
+            Self::Sentinel(pool) => RedisConnection::Sentinel(pool.get().await?),
+            Self::Cluster(pool) => RedisConnection::Cluster(pool.get().await?),
+        })
+    }
+
+    /// Groups indices of `keys` so that every group can be sent as one multi-key
+    /// command or pipeline, in a cluster keys of a group share the hash slot
+    pub fn group_by_slot<'a>(&self, keys: impl Iterator<Item = &'a str>) -> Vec<Vec<usize>> {
+        match self {
+            Self::Cluster(_) => {
+                let mut slots = BTreeMap::<u16, Vec<usize>>::new();
+                for (index, key) in keys.enumerate() {
+                    slots
+                        .entry(get_slot(key.as_bytes()))
+                        .or_default()
+                        .push(index);
+                }
+                slots.into_values().collect()
+            }
+            _ => {
+                let indices: Vec<usize> = (0..keys.count()).collect();
+                if indices.is_empty() {
+                    vec![]
+                } else {
+                    vec![indices]
+                }
+            }
+        }
+    }
+}
+
+pub enum RedisConnection {
+    Single(deadpool_redis::Connection),
+    Sentinel(sentinel::Connection),
+    Cluster(cluster::Connection),
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            Self::Single(conn) => conn.req_packed_command(cmd),
+            Self::Sentinel(conn) => conn.req_packed_command(cmd),
+            Self::Cluster(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<redis::Value>> {
+        match self {
+            Self::Single(conn) => conn.req_packed_commands(cmd, offset, count),
+            Self::Sentinel(conn) => conn.req_packed_commands(cmd, offset, count),
+            Self::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            Self::Single(conn) => conn.get_db(),
+            Self::Sentinel(conn) => conn.get_db(),
+            Self::Cluster(conn) => conn.get_db(),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use {
+    crate::{
+        quota::{QuotaBackend, QuotaKey, QuotaLimits, QuotaUsage},
+        redis::{redis_pool::RedisPool, refreshing_fallback_cache::RefreshingFallbackCache},
+    },
+    deadpool_redis::redis,
+    std::{collections::HashMap, sync::Arc},
+};
+
+/// Usage hashes are kept for a while after the month ends for reconciliation
+const USAGE_TTL_SECS: i64 = 62 * 24 * 60 * 60;
+
+/// Reads `{redis_prefix}:{YYYY-MM}:{team_id}` keys, `CAPPED` or a ratio of `1.0` means
+/// the team is capped, a lower ratio is the used share of the quota.
+/// Limits are JSON values at `{redis_prefix}:limits:{team_id}`, usage is kept in
+/// `{redis_prefix}:usage:{YYYY-MM}:{team_id}` hashes with `bytes` and `messages` fields.
+#[derive(Debug)]
+pub struct RedisQuotaBackend {
+    cache: RefreshingFallbackCache<f64>,
+    limits_cache: RefreshingFallbackCache<Option<QuotaLimits>>,
+    redis_pool: Arc<RedisPool>,
+    redis_prefix: String,
+}
+
+impl RedisQuotaBackend {
+    pub fn new(
+        cache: RefreshingFallbackCache<f64>,
+        limits_cache: RefreshingFallbackCache<Option<QuotaLimits>>,
+        redis_prefix: String,
+    ) -> Self {
+        Self {
+            redis_pool: cache.redis_pool(),
+            cache,
+            limits_cache,
+            redis_prefix,
+        }
+    }
+
+    fn usage_key(&self, key: &QuotaKey) -> String {
+        format!("{}:usage:{}", self.redis_prefix, key.to_suffix())
+    }
+}
+
+#[tonic::async_trait]
+impl QuotaBackend for RedisQuotaBackend {
+    async fn usage_ratio(&self, keys: &[QuotaKey]) -> HashMap<QuotaKey, anyhow::Result<f64>> {
+        if let [key] = keys {
+            let result = self.cache.get_or_refresh(&key.to_suffix()).await;
+            return HashMap::from([(key.clone(), result)]);
+        }
+
+        let key_suffixes: Vec<String> = keys.iter().map(QuotaKey::to_suffix).collect();
+        let mut results = self.cache.get_many_or_refresh(&key_suffixes).await;
+        keys.iter()
+            .map(|key| {
+                let result = results
+                    .remove(&key.to_suffix())
+                    .unwrap_or_else(|| Err(anyhow::anyhow!("failed to fetch quota from redis")));
+                (key.clone(), result)
+            })
+            .collect()
+    }
+
+    async fn invalidate(&self, keys: &[QuotaKey]) {
+        for key in keys {
+            self.cache.invalidate(&key.to_suffix()).await;
+        }
+    }
+
+    async fn limits(
+        &self,
+        team_ids: &[String],
+    ) -> HashMap<String, anyhow::Result<Option<QuotaLimits>>> {
+        let mut results = self.limits_cache.get_many_or_refresh(team_ids).await;
+        team_ids
+            .iter()
+            .map(|team_id| {
+                let result = results
+                    .remove(team_id)
+                    .unwrap_or_else(|| Err(anyhow::anyhow!("failed to fetch limits from redis")));
+                (team_id.clone(), result)
+            })
+            .collect()
+    }
+
+    async fn load_usage(&self, keys: &[QuotaKey]) -> anyhow::Result<HashMap<QuotaKey, QuotaUsage>> {
+        if keys.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let usage_keys: Vec<String> = keys.iter().map(|key| self.usage_key(key)).collect();
+        let mut conn = self.redis_pool.get().await?;
+        let mut usage = HashMap::with_capacity(keys.len());
+        // a pipeline can only address a single hash slot in a cluster
+        for indices in self
+            .redis_pool
+            .group_by_slot(usage_keys.iter().map(String::as_str))
+        {
+            let mut pipe = redis::pipe();
+            for index in &indices {
+                pipe.hget(&usage_keys[*index], &["bytes", "messages"]);
+            }
+            let values: Vec<(Option<u64>, Option<u64>)> = pipe.query_async(&mut conn).await?;
+            for (index, (bytes, messages)) in indices.into_iter().zip(values) {
+                let value = QuotaUsage {
+                    bytes: bytes.unwrap_or(0),
+                    messages: messages.unwrap_or(0),
+                };
+                usage.insert(keys[index].clone(), value);
+            }
+        }
+        Ok(usage)
+    }
+
+    async fn add_usage(
+        &self,
+        deltas: &[(QuotaKey, QuotaUsage)],
+    ) -> anyhow::Result<HashMap<QuotaKey, QuotaUsage>> {
+        if deltas.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let usage_keys: Vec<String> = deltas.iter().map(|(key, _)| self.usage_key(key)).collect();
+        let mut conn = self.redis_pool.get().await?;
+        let mut usage = HashMap::with_capacity(deltas.len());
+        for indices in self
+            .redis_pool
+            .group_by_slot(usage_keys.iter().map(String::as_str))
+        {
+            let mut pipe = redis::pipe();
+            for index in &indices {
+                let usage_key = &usage_keys[*index];
+                let delta = deltas[*index].1;
+                pipe.hincr(usage_key, "bytes", delta.bytes)
+                    .hincr(usage_key, "messages", delta.messages)
+                    .expire(usage_key, USAGE_TTL_SECS)
+                    .ignore();
+            }
+            // two counters per key, `expire` replies are ignored
+            let totals: Vec<u64> = pipe.query_async(&mut conn).await?;
+            for (index, total) in indices.into_iter().zip(totals.chunks_exact(2)) {
+                let value = QuotaUsage {
+                    bytes: total[0],
+                    messages: total[1],
+                };
+                usage.insert(deltas[index].0.clone(), value);
+            }
+        }
+        Ok(usage)
+    }
+}
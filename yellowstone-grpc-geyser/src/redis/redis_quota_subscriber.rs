@@ -0,0 +1,156 @@
+use {
+    crate::{
+        config::{ConfigGrpc, ConfigRedisPushMode},
+        metrics::{QUOTA_PUSH_EVENTS, TEAMS_CAPPED},
+        quota::{
+            current_year_month, is_capped_ratio, quota_warner::QuotaWarner, QuotaBackend, QuotaKey,
+        },
+        user_connection::connection_manager::{ConnectionManager, ShutdownReason},
+    },
+    anyhow::Context,
+    deadpool_redis::redis::Client,
+    futures::stream::StreamExt,
+    log::{error, info, warn},
+    std::sync::Arc,
+    tokio::time::{sleep, Duration},
+};
+
+const RECONNECT_DELAY_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+enum Subscription {
+    Channel(String),
+    Keyspace(String),
+}
+
+/// Applies cap changes pushed by Redis without waiting for the next quota checker run
+#[derive(Debug)]
+pub struct RedisQuotaSubscriber {
+    client: Client,
+    subscription: Subscription,
+    redis_prefix: String,
+}
+
+impl RedisQuotaSubscriber {
+    pub fn new(config: &ConfigGrpc) -> anyhow::Result<Option<Self>> {
+        let subscription = match config.redis_push_mode {
+            ConfigRedisPushMode::Disabled => return Ok(None),
+            ConfigRedisPushMode::Pubsub => Subscription::Channel(
+                config
+                    .redis_push_channel
+                    .clone()
+                    .unwrap_or_else(|| format!("{}:events", config.redis_prefix)),
+            ),
+            ConfigRedisPushMode::Keyspace => {
+                Subscription::Keyspace(format!("__keyspace@*__:{}:*", config.redis_prefix))
+            }
+        };
+        let redis_url = config
+            .redis_url
+            .as_deref()
+            .context("redis_url is required for redis push mode")?;
+
+        Ok(Some(Self {
+            client: Client::open(redis_url).context("invalid redis_url")?,
+            subscription,
+            redis_prefix: config.redis_prefix.clone(),
+        }))
+    }
+
+    pub async fn run(
+        self,
+        backend: Arc<dyn QuotaBackend>,
+        manager: Arc<ConnectionManager>,
+        warner: Arc<QuotaWarner>,
+    ) {
+        let mut delay = RECONNECT_DELAY_MIN;
+        loop {
+            match self.subscribe(backend.as_ref(), &manager, &warner).await {
+                Ok(()) => {
+                    warn!("redis quota subscription closed, reconnecting");
+                    delay = RECONNECT_DELAY_MIN;
+                }
+                Err(error) => {
+                    error!("redis quota subscription failed: {error:?}");
+                    delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+                }
+            }
+            sleep(delay).await;
+        }
+    }
+
+    async fn subscribe(
+        &self,
+        backend: &dyn QuotaBackend,
+        manager: &ConnectionManager,
+        warner: &QuotaWarner,
+    ) -> anyhow::Result<()> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        match &self.subscription {
+            Subscription::Channel(channel) => pubsub.subscribe(channel).await?,
+            Subscription::Keyspace(pattern) => pubsub.psubscribe(pattern).await?,
+        }
+        info!("subscribed to redis quota events: {:?}", self.subscription);
+
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let key_suffix = match &self.subscription {
+                Subscription::Channel(_) => match message.get_payload::<String>() {
+                    Ok(payload) => payload,
+                    Err(error) => {
+                        warn!("invalid redis quota event payload: {error}");
+                        continue;
+                    }
+                },
+                Subscription::Keyspace(_) => {
+                    // `__keyspace@{db}__:{redis_prefix}:{key_suffix}`
+                    let Some(key_suffix) = message
+                        .get_channel_name()
+                        .split_once("__:")
+                        .and_then(|(_, key)| key.strip_prefix(self.redis_prefix.as_str()))
+                        .and_then(|key| key.strip_prefix(':'))
+                    else {
+                        continue;
+                    };
+                    key_suffix.to_owned()
+                }
+            };
+
+            // limits and usage keys share the prefix, only current period caps are applied
+            match QuotaKey::from_suffix(key_suffix.trim()) {
+                Some(key) if key.year_month == current_year_month() => {
+                    QUOTA_PUSH_EVENTS.inc();
+                    Self::apply(backend, manager, warner, key).await;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn apply(
+        backend: &dyn QuotaBackend,
+        manager: &ConnectionManager,
+        warner: &QuotaWarner,
+        key: QuotaKey,
+    ) {
+        backend.invalidate(std::slice::from_ref(&key)).await;
+        let result = backend
+            .usage_ratio(std::slice::from_ref(&key))
+            .await
+            .remove(&key);
+        match result {
+            Some(Ok(usage_ratio)) if is_capped_ratio(usage_ratio) => {
+                TEAMS_CAPPED.inc();
+                info!("Team {} is capped, shutting down connection", key.team_id);
+                manager.shutdown_team(&key.team_id, ShutdownReason::quota_exceeded());
+            }
+            Some(Ok(usage_ratio)) => warner.observe(&key.team_id, usage_ratio),
+            Some(Err(error)) => {
+                error!("Failed to check quota for team {}: {error:?}", key.team_id);
+            }
+            None => {}
+        }
+    }
+}
@@ -1,184 +1,502 @@
 use std::collections::HashMap;
 use {
+    crate::{
+        config::ConfigGrpc,
+        metrics::{REDIS_CACHE_ERRORS, REDIS_CACHE_LOOKUPS, REDIS_CACHE_REFRESHES},
+        redis::redis_pool::RedisPool,
+    },
     anyhow::Result,
     deadpool_redis::redis::AsyncCommands,
-    deadpool_redis::{Config, Connection, Pool, Runtime},
-    log::error,
+    futures::future::{join_all, BoxFuture, FutureExt, Shared},
+    log::{error, warn},
     moka::future::Cache,
     std::{
-        sync::Arc,
+        fmt,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
         time::{Duration, Instant},
     },
+    tokio::sync::Mutex as AsyncMutex,
 };
 
+/// Fetch of a single key shared by every lookup that waits for it
+type Fetch<V> = Shared<BoxFuture<'static, Result<V, Arc<anyhow::Error>>>>;
+
+/// Fetch of a key, only the batch with the current `id` may update the cache
+struct InFlight<V> {
+    id: u64,
+    fetch: Fetch<V>,
+}
+
 #[derive(Clone)]
 pub struct RefreshingFallbackCache<V>
 where
     V: Clone + Send + Sync + 'static,
 {
-    redis_pool: Arc<Pool>,
+    name: &'static str,
+    redis_pool: Arc<RedisPool>,
     cache: Cache<String, Arc<CachedValue<V>>>,
+    in_flight: Arc<Mutex<HashMap<String, InFlight<V>>>>,
+    next_fetch_id: Arc<AtomicU64>,
+    /// Serializes cache updates of fetches with invalidations
+    writes: Arc<AsyncMutex<()>>,
     redis_prefix: String,
     ttl: Duration,
+    negative_ttl: Duration,
     background_buffer: Duration,
+    max_stale: Duration,
     value_parser: Arc<dyn Fn(Option<String>) -> V + Send + Sync>,
 }
 
+impl<V> fmt::Debug for RefreshingFallbackCache<V>
+where
+    V: Clone + Send + Sync + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RefreshingFallbackCache")
+            .field("name", &self.name)
+            .field("redis_prefix", &self.redis_prefix)
+            .field("ttl", &self.ttl)
+            .field("negative_ttl", &self.negative_ttl)
+            .field("background_buffer", &self.background_buffer)
+            .field("max_stale", &self.max_stale)
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug)]
 struct CachedValue<V> {
     value: V,
     fetched_at: Instant,
+    /// `negative_ttl` for keys missing in Redis, `ttl` otherwise
+    ttl: Duration,
+}
+
+#[derive(Debug)]
+enum Lookup<V> {
+    Fresh(V),
+    /// Served from cache while a background refresh runs
+    Stale(V),
+    /// Expired or missing, the expired value is kept as a fallback for errors
+    Miss(Option<Arc<CachedValue<V>>>),
 }
 
 impl<V> RefreshingFallbackCache<V>
 where
     V: Clone + Send + Sync + 'static,
 {
-    pub async fn new(
-        redis_url: &str,
+    pub fn new(
+        name: &'static str,
+        redis_pool: Arc<RedisPool>,
         redis_prefix: String,
-        ttl: Duration,
-        capacity: usize,
-        background_buffer: Duration,
+        config: &ConfigGrpc,
         value_parser: Arc<dyn Fn(Option<String>) -> V + Send + Sync>,
-    ) -> Result<Self> {
-        let cfg = Config::from_url(redis_url);
-        let pool = cfg.create_pool(Some(Runtime::Tokio1))?;
-
-        let cache = Cache::new(capacity);
-
-        Ok(Self {
-            redis_pool: Arc::new(pool),
-            cache,
+    ) -> Self {
+        Self {
+            name,
+            redis_pool,
+            cache: Cache::new(config.redis_cache_capacity),
+            in_flight: Arc::default(),
+            next_fetch_id: Arc::default(),
+            writes: Arc::default(),
             redis_prefix,
-            ttl,
-            background_buffer,
+            ttl: config.redis_cache_ttl,
+            negative_ttl: config.redis_cache_negative_ttl,
+            background_buffer: config.redis_background_buffer,
+            max_stale: config.redis_cache_max_stale,
             value_parser,
-        })
+        }
     }
 
+    pub fn redis_pool(&self) -> Arc<RedisPool> {
+        Arc::clone(&self.redis_pool)
+    }
+
+    /// Drops the cached value so the next lookup goes to Redis, a fetch that is
+    /// already running is not reused and doesn't update the cache
+    pub async fn invalidate(&self, key_suffix: &str) {
+        let _writes = self.writes.lock().await;
+        self.in_flight.lock().unwrap().remove(key_suffix);
+        self.cache.invalidate(&key_suffix.to_string()).await;
+    }
+
+    /// Errors are only returned when Redis fails and no value younger than
+    /// `ttl` + `max_stale` is cached, callers decide whether to fail open or closed
     pub async fn get_or_refresh(&self, key_suffix: &str) -> Result<V> {
-        let now = Instant::now();
         let key = key_suffix.to_string();
+        match self.lookup(&key) {
+            Lookup::Fresh(value) => Ok(value),
+            Lookup::Stale(value) => {
+                self.refresh(std::slice::from_ref(&key));
+                Ok(value)
+            }
+            Lookup::Miss(cached) => {
+                let fetch = self
+                    .refresh(std::slice::from_ref(&key))
+                    .pop()
+                    .expect("one fetch per key");
+                let result = fetch.await;
+                self.fallback(&key, cached, result)
+            }
+        }
+    }
 
-        if let Some(cached) = self.cache.get(&key) {
-            let age = now.duration_since(cached.fetched_at);
+    pub async fn get_many_or_refresh(&self, key_suffixes: &[String]) -> HashMap<String, Result<V>> {
+        let mut results = HashMap::with_capacity(key_suffixes.len());
+        let mut stale = Vec::new();
+        let mut misses = Vec::new();
 
-            if age < self.ttl {
-                return Ok(cached.value.clone());
-            }
-            if age < self.ttl + self.background_buffer {
-                self.trigger_background_refresh(key.clone());
-                return Ok(cached.value.clone());
+        // 1. Check cache
+        for key_suffix in key_suffixes {
+            match self.lookup(key_suffix) {
+                Lookup::Fresh(value) => {
+                    results.insert(key_suffix.clone(), Ok(value));
+                }
+                Lookup::Stale(value) => {
+                    results.insert(key_suffix.clone(), Ok(value));
+                    stale.push(key_suffix.clone());
+                }
+                Lookup::Miss(cached) => misses.push((key_suffix.clone(), cached)),
             }
         }
 
-        let value = self.fetch_and_update(&key).await?;
-        Ok(value)
+        // 2. Refresh stale keys in background, wait for missing/expired keys
+        if !stale.is_empty() {
+            self.refresh(&stale);
+        }
+        let to_fetch: Vec<String> = misses.iter().map(|(key, _)| key.clone()).collect();
+        let fetched = join_all(self.refresh(&to_fetch)).await;
+        for ((key_suffix, cached), result) in misses.into_iter().zip(fetched) {
+            let result = self.fallback(&key_suffix, cached, result);
+            results.insert(key_suffix, result);
+        }
+
+        results
     }
 
-    fn trigger_background_refresh(&self, key: String) {
-        let redis_pool = Arc::clone(&self.redis_pool);
-        let redis_key = format!("{}:{}", self.redis_prefix, key);
-        let cache = self.cache.clone();
-        let parser = Arc::clone(&self.value_parser);
-
-        tokio::spawn(async move {
-            if let Ok(mut conn) = redis_pool.get().await {
-                match conn.get::<_, Option<String>>(&redis_key).await {
-                    Ok(result) => {
-                        let parsed_value = parser(result);
-                        let cached = Arc::new(CachedValue {
-                            value: parsed_value,
-                            fetched_at: Instant::now(),
-                        });
-                        let _ = cache.insert(key, cached).await;
-                    }
-                    Err(e) => {
-                        error!(
-                            "Background Redis refresh error for key {}: {:?}",
-                            redis_key, e
-                        );
-                    }
+    fn lookup(&self, key_suffix: &String) -> Lookup<V> {
+        let lookup = match self.cache.get(key_suffix) {
+            Some(cached) => {
+                let age = cached.fetched_at.elapsed();
+                if age < cached.ttl {
+                    Lookup::Fresh(cached.value.clone())
+                } else if age < cached.ttl + self.background_buffer {
+                    Lookup::Stale(cached.value.clone())
+                } else {
+                    Lookup::Miss(Some(cached))
                 }
             }
-        });
+            None => Lookup::Miss(None),
+        };
+        let result = match lookup {
+            Lookup::Fresh(_) => "hit",
+            Lookup::Stale(_) => "stale",
+            Lookup::Miss(_) => "miss",
+        };
+        REDIS_CACHE_LOOKUPS
+            .with_label_values(&[self.name, result])
+            .inc();
+        lookup
     }
 
-    async fn fetch_and_update(&self, key: &str) -> Result<V> {
-        let redis_key = format!("{}:{}", self.redis_prefix, key);
-        let mut conn: Connection = self.redis_pool.get().await?;
+    /// Serves the last known value if the fetch failed and it is not too old
+    fn fallback(
+        &self,
+        key_suffix: &str,
+        cached: Option<Arc<CachedValue<V>>>,
+        result: Result<V, Arc<anyhow::Error>>,
+    ) -> Result<V> {
+        let error = match result {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        match cached {
+            Some(cached) if cached.fetched_at.elapsed() < cached.ttl + self.max_stale => {
+                REDIS_CACHE_LOOKUPS
+                    .with_label_values(&[self.name, "fallback"])
+                    .inc();
+                warn!(
+                    "serving stale {} value for key {key_suffix}: {error:?}",
+                    self.name
+                );
+                Ok(cached.value.clone())
+            }
+            _ => Err(anyhow::anyhow!(
+                "failed to fetch {} for key {key_suffix} from redis: {error:?}",
+                self.name
+            )),
+        }
+    }
 
-        let result: Option<String> = conn.get(&redis_key).await.ok();
+    /// Starts fetches for the keys that are not already being fetched, one MGET per
+    /// hash slot in a cluster. Fetches run in their own tasks, so they complete and
+    /// update the cache even if no lookup waits for them
+    fn refresh(&self, key_suffixes: &[String]) -> Vec<Fetch<V>> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let mut fetches: Vec<Option<Fetch<V>>> = key_suffixes
+            .iter()
+            .map(|key_suffix| {
+                in_flight
+                    .get(key_suffix)
+                    .map(|in_flight| in_flight.fetch.clone())
+            })
+            .collect();
 
-        let parsed_value = (self.value_parser)(result);
-        let cached = Arc::new(CachedValue {
-            value: parsed_value.clone(),
-            fetched_at: Instant::now(),
-        });
+        let missing: Vec<usize> = (0..key_suffixes.len())
+            .filter(|index| fetches[*index].is_none())
+            .collect();
+        let redis_keys: Vec<String> = missing
+            .iter()
+            .map(|index| format!("{}:{}", self.redis_prefix, key_suffixes[*index]))
+            .collect();
+        for group in self
+            .redis_pool
+            .group_by_slot(redis_keys.iter().map(String::as_str))
+        {
+            let keys: Vec<String> = group
+                .iter()
+                .map(|index| key_suffixes[missing[*index]].clone())
+                .collect();
+            let id = self.next_fetch_id.fetch_add(1, Ordering::Relaxed);
+            let this = self.clone();
+            let batch = async move { this.fetch_and_update(id, keys).await }
+                .boxed()
+                .shared();
+            tokio::spawn(batch.clone());
 
-        self.cache.insert(key.to_string(), cached).await;
+            for (position, index) in group.into_iter().enumerate() {
+                let fetch = batch
+                    .clone()
+                    .map(move |result| result.map(|values| values[position].clone()))
+                    .boxed()
+                    .shared();
+                let key_suffix = &key_suffixes[missing[index]];
+                in_flight.insert(
+                    key_suffix.clone(),
+                    InFlight {
+                        id,
+                        fetch: fetch.clone(),
+                    },
+                );
+                fetches[missing[index]] = Some(fetch);
+            }
+        }
 
-        Ok(parsed_value)
+        fetches
+            .into_iter()
+            .map(|fetch| fetch.expect("fetch started for every key"))
+            .collect()
     }
 
-    pub async fn get_many_or_refresh(
+    async fn fetch_and_update(
         &self,
-        key_suffixes: &[String],
-    ) -> HashMap<String, Result<V>> {
-        let now = Instant::now();
-        let mut results = HashMap::with_capacity(key_suffixes.len());
-        let mut to_fetch = Vec::new();
+        id: u64,
+        key_suffixes: Vec<String>,
+    ) -> Result<Arc<Vec<V>>, Arc<anyhow::Error>> {
+        REDIS_CACHE_REFRESHES
+            .with_label_values(&[self.name])
+            .inc_by(key_suffixes.len() as u64);
+        let redis_keys: Vec<String> = key_suffixes
+            .iter()
+            .map(|key_suffix| format!("{}:{}", self.redis_prefix, key_suffix))
+            .collect();
 
-        // 1. Check cache
-        for key_suffix in key_suffixes {
-            if let Some(cached) = self.cache.get(key_suffix) {
-                let age = now.duration_since(cached.fetched_at);
-                if age < self.ttl {
-                    results.insert(key_suffix.clone(), Ok(cached.value.clone()));
-                    continue;
-                }
-                if age < self.ttl + self.background_buffer {
-                    self.trigger_background_refresh(key_suffix.clone());
-                    results.insert(key_suffix.clone(), Ok(cached.value.clone()));
-                    continue;
+        let fetched: Result<Vec<Option<String>>> = async {
+            let mut conn = self.redis_pool.get().await?;
+            // MGET of a single key is sent as GET, which doesn't reply with an array
+            Ok(match redis_keys.as_slice() {
+                [redis_key] => vec![conn.get(redis_key).await?],
+                _ => conn.mget(&redis_keys).await?,
+            })
+        }
+        .await;
+
+        let _writes = self.writes.lock().await;
+        // keys invalidated since the fetch started are fetched again by the next lookup
+        let current: Vec<bool> = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            key_suffixes
+                .iter()
+                .map(|key_suffix| {
+                    let current = in_flight
+                        .get(key_suffix)
+                        .is_some_and(|in_flight| in_flight.id == id);
+                    if current && fetched.is_err() {
+                        in_flight.remove(key_suffix);
+                    }
+                    current
+                })
+                .collect()
+        };
+
+        match fetched {
+            Ok(raw_values) => {
+                let mut values = Vec::with_capacity(raw_values.len());
+                for ((key_suffix, raw), current) in key_suffixes.iter().zip(raw_values).zip(current)
+                {
+                    let ttl = if raw.is_some() {
+                        self.ttl
+                    } else {
+                        self.negative_ttl
+                    };
+                    let value = (self.value_parser)(raw);
+                    if current {
+                        let cached = Arc::new(CachedValue {
+                            value: value.clone(),
+                            fetched_at: Instant::now(),
+                            ttl,
+                        });
+                        self.cache.insert(key_suffix.clone(), cached).await;
+                        // the cache is updated first, so later lookups don't start another fetch
+                        self.in_flight.lock().unwrap().remove(key_suffix);
+                    }
+                    values.push(value);
                 }
+                Ok(Arc::new(values))
+            }
+            Err(error) => {
+                REDIS_CACHE_ERRORS
+                    .with_label_values(&[self.name])
+                    .inc_by(key_suffixes.len() as u64);
+                error!("Redis refresh error for keys {:?}: {:?}", redis_keys, error);
+                Err(Arc::new(error))
             }
+        }
+    }
+}
 
-            to_fetch.push(key_suffix.clone());
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        tokio::{
+            io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+            net::{tcp::OwnedReadHalf, TcpListener},
+            sync::{mpsc, Notify},
+        },
+    };
+
+    /// Values of a minimal Redis server, the first GET waits for `gate`
+    #[derive(Default)]
+    struct FakeRedis {
+        values: Mutex<HashMap<String, String>>,
+        gate: Notify,
+        gated: Mutex<bool>,
+    }
+
+    async fn read_command(reader: &mut BufReader<OwnedReadHalf>) -> Option<Vec<String>> {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.ok()? == 0 {
+            return None;
         }
+        let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            // bulk length, then the bulk string
+            line.clear();
+            reader.read_line(&mut line).await.ok()?;
+            line.clear();
+            reader.read_line(&mut line).await.ok()?;
+            args.push(line.trim_end().to_owned());
+        }
+        Some(args)
+    }
 
-        // 2. Batch fetch Redis for missing/stale keys
-        if !to_fetch.is_empty() {
-            if let Ok(mut conn) = self.redis_pool.get().await {
-                let redis_keys: Vec<String> = to_fetch
-                    .iter()
-                    .map(|k| format!("{}:{}", self.redis_prefix, k))
-                    .collect();
-
-                let fetched: Result<Vec<Option<String>>, _> = conn.mget(redis_keys).await;
-
-                match fetched {
-                    Ok(values) => {
-                        for (key_suffix, raw) in to_fetch.into_iter().zip(values) {
-                            let parsed = (self.value_parser)(raw);
-                            let cached = Arc::new(CachedValue {
-                                value: parsed.clone(),
-                                fetched_at: Instant::now(),
-                            });
-                            let _ = self.cache.insert(key_suffix.clone(), cached).await;
-                            results.insert(key_suffix, Ok(parsed));
+    async fn serve(
+        listener: TcpListener,
+        redis: Arc<FakeRedis>,
+        gets: mpsc::UnboundedSender<String>,
+    ) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let (redis, gets) = (Arc::clone(&redis), gets.clone());
+            tokio::spawn(async move {
+                let mut reader = BufReader::new(read);
+                while let Some(args) = read_command(&mut reader).await {
+                    let reply = match args[0].to_uppercase().as_str() {
+                        "GET" => {
+                            let value = redis.values.lock().unwrap().get(&args[1]).cloned();
+                            let _ = gets.send(args[1].clone());
+                            let gated = std::mem::take(&mut *redis.gated.lock().unwrap());
+                            if gated {
+                                redis.gate.notified().await;
+                            }
+                            match value {
+                                Some(value) => format!("${}\r\n{value}\r\n", value.len()),
+                                None => "$-1\r\n".to_owned(),
+                            }
                         }
-                    }
-                    Err(err) => {
-                        for key_suffix in to_fetch {
-                            results.insert(key_suffix, Err(anyhow::anyhow!(err.to_string())));
+                        "PING" if args.len() > 1 => {
+                            format!("${}\r\n{}\r\n", args[1].len(), args[1])
                         }
+                        "PING" => "+PONG\r\n".to_owned(),
+                        _ => "+OK\r\n".to_owned(),
+                    };
+                    if write.write_all(reply.as_bytes()).await.is_err() {
+                        break;
                     }
                 }
-            }
+            });
         }
+    }
 
-        results
+    #[tokio::test]
+    async fn test_invalidate_pending_fetch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config: ConfigGrpc = serde_json::from_value(serde_json::json!({
+            "address": "127.0.0.1:0",
+            "network": { "name": "SOLANA_MAINNET" },
+            "redis_url": format!("redis://{}", listener.local_addr().unwrap()),
+        }))
+        .unwrap();
+        let redis = Arc::new(FakeRedis::default());
+        let (gets_tx, mut gets_rx) = mpsc::unbounded_channel();
+        tokio::spawn(serve(listener, Arc::clone(&redis), gets_tx));
+
+        let cache = RefreshingFallbackCache::new(
+            "test",
+            Arc::new(RedisPool::new(&config).unwrap()),
+            "prefix".to_owned(),
+            &config,
+            Arc::new(|value: Option<String>| value),
+        );
+        redis
+            .values
+            .lock()
+            .unwrap()
+            .insert("prefix:team".to_owned(), "old".to_owned());
+        *redis.gated.lock().unwrap() = true;
+
+        // the fetch reads the old value and stalls before replying
+        let pending = tokio::spawn({
+            let cache = cache.clone();
+            async move { cache.get_or_refresh("team").await.unwrap() }
+        });
+        assert_eq!(gets_rx.recv().await.unwrap(), "prefix:team");
+
+        redis
+            .values
+            .lock()
+            .unwrap()
+            .insert("prefix:team".to_owned(), "new".to_owned());
+        cache.invalidate("team").await;
+        // reusing the stalled fetch would wait for the gate
+        let value = tokio::time::timeout(Duration::from_secs(5), cache.get_or_refresh("team"))
+            .await
+            .expect("lookup reused the outdated fetch")
+            .unwrap();
+        assert_eq!(value.as_deref(), Some("new"));
+
+        // the outdated fetch completes but doesn't overwrite the new value
+        redis.gate.notify_one();
+        assert_eq!(pending.await.unwrap().as_deref(), Some("old"));
+        assert_eq!(
+            cache.get_or_refresh("team").await.unwrap().as_deref(),
+            Some("new")
+        );
+        assert!(cache.in_flight.lock().unwrap().is_empty());
     }
 }
@@ -0,0 +1,219 @@
+use {
+    crate::{
+        config::{Config, ConfigGrpc, QuotaFailurePolicy},
+        user_connection::connection_manager::StreamLimits,
+    },
+    agave_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPluginError, Result as PluginResult,
+    },
+    anyhow::Context,
+    log::{error, info},
+    serde_json::Value,
+    std::{
+        collections::BTreeSet,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::SystemTime,
+    },
+    tokio::{
+        fs,
+        sync::watch,
+        time::{interval, Duration, MissedTickBehavior},
+    },
+    yellowstone_grpc_proto::plugin::filter::limits::FilterLimits,
+};
+
+/// Config keys applied to running services by `ConfigReloader::reload`,
+/// `grpc.filters` is the alias of `grpc.filter_limits`
+const RELOADABLE_KEYS: &[&str] = &[
+    "log",
+    "grpc.filter_limits",
+    "grpc.filters",
+    "grpc.x_token",
+    "grpc.billing_ticker_interval",
+    "grpc.quota_check_interval",
+    "grpc.quota_check_batch_size",
+    "grpc.quota_unavailable_policy",
+    "grpc.quota_warning_thresholds",
+    "grpc.max_streams_per_team",
+    "grpc.max_streams_per_app",
+    "grpc.throttle_bytes_per_second",
+    "grpc.throttle_burst_bytes",
+];
+
+/// Part of `ConfigGrpc` that running services read on use instead of copying it on startup
+#[derive(Debug, Clone)]
+pub struct LiveConfig {
+    pub filter_limits: Arc<FilterLimits>,
+    pub x_token: Option<String>,
+    /// Applied to streams opened after a reload
+    pub billing_ticker_interval: Duration,
+    pub quota_check_interval: Duration,
+    pub quota_check_batch_size: usize,
+    pub quota_unavailable_policy: QuotaFailurePolicy,
+    /// Sorted, values outside of `(0, 1)` are dropped
+    pub quota_warning_thresholds: Vec<f64>,
+    pub stream_limits: StreamLimits,
+    pub throttle_bytes_per_second: Option<u64>,
+    pub throttle_burst_bytes: Option<u64>,
+}
+
+impl LiveConfig {
+    pub fn new(config: &ConfigGrpc) -> Self {
+        let mut quota_warning_thresholds = config.quota_warning_thresholds.clone();
+        quota_warning_thresholds.retain(|threshold| *threshold > 0.0 && *threshold < 1.0);
+        quota_warning_thresholds.sort_by(f64::total_cmp);
+        Self {
+            filter_limits: Arc::new(config.filter_limits.clone()),
+            x_token: config.x_token.clone(),
+            billing_ticker_interval: config.billing_ticker_interval,
+            quota_check_interval: config.quota_check_interval,
+            quota_check_batch_size: config.quota_check_batch_size.max(1),
+            quota_unavailable_policy: config.quota_unavailable_policy,
+            quota_warning_thresholds,
+            stream_limits: StreamLimits {
+                per_team: config.max_streams_per_team,
+                per_app: config.max_streams_per_app,
+            },
+            throttle_bytes_per_second: config.throttle_bytes_per_second,
+            throttle_burst_bytes: config.throttle_burst_bytes,
+        }
+    }
+}
+
+/// Changed config keys found by a reload
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConfigReloadResult {
+    /// Reloadable keys changed since the previous reload, now in use
+    pub applied: Vec<String>,
+    /// Keys changed since the plugin was loaded which need a plugin reload
+    pub not_reloadable: Vec<String>,
+}
+
+/// Re-reads the plugin config file and publishes `LiveConfig` to running services
+#[derive(Debug)]
+pub struct ConfigReloader {
+    path: PathBuf,
+    /// Config file as the plugin was loaded with
+    loaded: Value,
+    /// Config file of the last reload
+    applied: Mutex<Value>,
+    modified: Mutex<Option<SystemTime>>,
+    live_tx: watch::Sender<Arc<LiveConfig>>,
+}
+
+impl ConfigReloader {
+    pub fn new(path: impl AsRef<Path>) -> PluginResult<(Self, Config)> {
+        let path = path.as_ref().to_path_buf();
+        let modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let content =
+            std::fs::read_to_string(&path).map_err(GeyserPluginError::ConfigFileOpenError)?;
+        let config = Config::load_from_str(&content)?;
+        let loaded = serde_json::from_str::<Value>(&content).map_err(|error| {
+            GeyserPluginError::ConfigFileReadError {
+                msg: error.to_string(),
+            }
+        })?;
+
+        let (live_tx, _) = watch::channel(Arc::new(LiveConfig::new(&config.grpc)));
+        let reloader = Self {
+            path,
+            applied: Mutex::new(loaded.clone()),
+            loaded,
+            modified: Mutex::new(modified),
+            live_tx,
+        };
+        Ok((reloader, config))
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Arc<LiveConfig>> {
+        self.live_tx.subscribe()
+    }
+
+    /// Applies reloadable keys of the config file, the file is rejected as a whole
+    /// if it isn't a valid config
+    pub async fn reload(&self) -> anyhow::Result<ConfigReloadResult> {
+        let content = fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("failed to read config file {:?}", self.path))?;
+        let config = Config::load_from_str(&content).context("invalid config")?;
+        let value = serde_json::from_str::<Value>(&content).context("invalid config")?;
+
+        let mut applied = self.applied.lock().unwrap();
+        let result = ConfigReloadResult {
+            applied: changed_keys(&applied, &value)
+                .into_iter()
+                .filter(|key| RELOADABLE_KEYS.contains(&key.as_str()))
+                .collect(),
+            not_reloadable: changed_keys(&self.loaded, &value)
+                .into_iter()
+                .filter(|key| !RELOADABLE_KEYS.contains(&key.as_str()))
+                .collect(),
+        };
+        if result.applied.iter().any(|key| key == "log") {
+            solana_logger::setup_with_default(&config.log.level);
+        }
+        self.live_tx
+            .send_replace(Arc::new(LiveConfig::new(&config.grpc)));
+        *applied = value;
+        Ok(result)
+    }
+
+    /// Reloads the config once the file is modified
+    pub async fn run(self: Arc<Self>, check_interval: Duration) {
+        let mut ticker = interval(check_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let modified = fs::metadata(&self.path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            if modified == *self.modified.lock().unwrap() {
+                continue;
+            }
+
+            match self.reload().await {
+                Ok(result) => {
+                    info!(
+                        "reloaded config, applied: {:?}, not reloadable: {:?}",
+                        result.applied, result.not_reloadable
+                    );
+                    *self.modified.lock().unwrap() = modified;
+                }
+                // retried on the next tick, the file may be partially written
+                Err(error) => error!("failed to reload config: {error:?}"),
+            }
+        }
+    }
+}
+
+/// Top level keys and keys of `grpc` with a different value, a missing key
+/// is only equal to another missing key
+fn changed_keys(old: &Value, new: &Value) -> Vec<String> {
+    let mut changed = vec![];
+    for key in object_keys(old, new) {
+        if key == "grpc" {
+            let (old, new) = (&old[&key], &new[&key]);
+            changed.extend(
+                object_keys(old, new)
+                    .into_iter()
+                    .filter(|key| old.get(key) != new.get(key))
+                    .map(|key| format!("grpc.{key}")),
+            );
+        } else if old.get(&key) != new.get(&key) {
+            changed.push(key);
+        }
+    }
+    changed
+}
+
+fn object_keys(old: &Value, new: &Value) -> BTreeSet<String> {
+    [old, new]
+        .into_iter()
+        .filter_map(Value::as_object)
+        .flat_map(|object| object.keys().cloned())
+        .collect()
+}
@@ -0,0 +1,173 @@
+use {
+    crate::config::ConfigGrpcServerTls,
+    anyhow::Context,
+    futures::stream::{Stream, StreamExt},
+    log::{debug, error, info, warn},
+    std::{
+        io,
+        sync::{Arc, Mutex, RwLock},
+        time::SystemTime,
+    },
+    tokio::{
+        fs,
+        net::TcpStream,
+        sync::mpsc,
+        time::{interval, timeout, Duration, MissedTickBehavior},
+    },
+    tokio_rustls::{
+        rustls::{crypto::ring, server::WebPkiClientVerifier, RootCertStore, ServerConfig},
+        server::TlsStream,
+        TlsAcceptor,
+    },
+    tokio_stream::wrappers::ReceiverStream,
+    tonic::transport::server::TcpIncoming,
+};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Server TLS config that is rebuilt once the certificate, key or client CA files change,
+/// established connections keep the config they were accepted with
+#[derive(Debug)]
+pub struct ReloadableTls {
+    config: ConfigGrpcServerTls,
+    modified: Mutex<Vec<Option<SystemTime>>>,
+    server_config: RwLock<Arc<ServerConfig>>,
+}
+
+impl ReloadableTls {
+    pub async fn new(config: ConfigGrpcServerTls) -> anyhow::Result<Self> {
+        let modified = Self::modified(&config).await;
+        let server_config = Self::load(&config).await?;
+        Ok(Self {
+            config,
+            modified: Mutex::new(modified),
+            server_config: RwLock::new(Arc::new(server_config)),
+        })
+    }
+
+    fn paths(config: &ConfigGrpcServerTls) -> impl Iterator<Item = &String> {
+        [&config.cert_path, &config.key_path]
+            .into_iter()
+            .chain(config.client_ca_path.as_ref())
+    }
+
+    async fn modified(config: &ConfigGrpcServerTls) -> Vec<Option<SystemTime>> {
+        let mut modified = vec![];
+        for path in Self::paths(config) {
+            let metadata = fs::metadata(path).await.ok();
+            modified.push(metadata.and_then(|metadata| metadata.modified().ok()));
+        }
+        modified
+    }
+
+    async fn load(config: &ConfigGrpcServerTls) -> anyhow::Result<ServerConfig> {
+        let (cert, key) = tokio::try_join!(fs::read(&config.cert_path), fs::read(&config.key_path))
+            .context("failed to load tls_config files")?;
+        let certs = rustls_pemfile::certs(&mut cert.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to parse tls certificate")?;
+        let key = rustls_pemfile::private_key(&mut key.as_slice())
+            .context("failed to parse tls key")?
+            .context("no private key in tls key file")?;
+
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .context("failed to select tls versions")?;
+        let builder = match &config.client_ca_path {
+            Some(path) => {
+                let ca = fs::read(path)
+                    .await
+                    .with_context(|| format!("failed to read client CA file {path:?}"))?;
+                let mut roots = RootCertStore::empty();
+                for cert in rustls_pemfile::certs(&mut ca.as_slice()) {
+                    let cert = cert.context("failed to parse client CA certificate")?;
+                    roots.add(cert).context("invalid client CA certificate")?;
+                }
+                let mut verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+                if config.client_auth_optional {
+                    verifier = verifier.allow_unauthenticated();
+                }
+                builder.with_client_cert_verifier(
+                    verifier
+                        .build()
+                        .context("failed to create client certificate verifier")?,
+                )
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut server_config = builder
+            .with_single_cert(certs, key)
+            .context("invalid tls certificate or key")?;
+        server_config.alpn_protocols = vec![b"h2".to_vec()];
+        Ok(server_config)
+    }
+
+    /// Rebuilds the config if any file was modified, on failure keeps the previous one
+    async fn reload_if_changed(&self) {
+        let modified = Self::modified(&self.config).await;
+        if modified == *self.modified.lock().unwrap() {
+            return;
+        }
+
+        // files are retried on the next tick, the key may be written after the certificate
+        match Self::load(&self.config).await {
+            Ok(server_config) => {
+                info!("reloaded tls certificate from {:?}", self.config.cert_path);
+                *self.server_config.write().unwrap() = Arc::new(server_config);
+                *self.modified.lock().unwrap() = modified;
+            }
+            Err(error) => error!("failed to reload tls config: {error:?}"),
+        }
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(Arc::clone(&self.server_config.read().unwrap()))
+    }
+
+    /// Accepts TLS connections on `incoming` and checks files for changes,
+    /// both stop once the returned stream is dropped
+    pub fn incoming(
+        self: Arc<Self>,
+        mut incoming: TcpIncoming,
+    ) -> impl Stream<Item = Result<TlsStream<TcpStream>, io::Error>> {
+        let (tx, rx) = mpsc::channel(64);
+        tokio::spawn(async move {
+            let mut ticker = interval(self.config.reload_interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                let stream = tokio::select! {
+                    () = tx.closed() => break,
+                    _ = ticker.tick() => {
+                        self.reload_if_changed().await;
+                        continue;
+                    }
+                    stream = incoming.next() => match stream {
+                        Some(Ok(stream)) => stream,
+                        Some(Err(error)) => {
+                            warn!("failed to accept connection: {error}");
+                            continue;
+                        }
+                        None => break,
+                    },
+                };
+
+                // handshakes run in their own tasks, so a slow client doesn't block accepting
+                let acceptor = self.acceptor();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send(Ok(stream)).await;
+                        }
+                        Ok(Err(error)) => debug!("tls handshake failed: {error}"),
+                        Err(_) => debug!("tls handshake timed out"),
+                    }
+                });
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+}
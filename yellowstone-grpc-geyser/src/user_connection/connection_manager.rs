@@ -1,47 +1,451 @@
 use {
-    crate::user_connection::connection_token::ConnectionToken,
-    dashmap::{mapref::entry::Entry, DashMap},
-    std::sync::Arc,
+    crate::{
+        metrics::{STREAMS_REJECTED, TEAM_STREAMS},
+        user_connection::connection_token::ConnectionToken,
+    },
+    dashmap::DashMap,
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        sync::Arc,
+        time::SystemTime,
+    },
+    thiserror::Error,
     tokio::sync::watch,
+    tonic::{Code, Status},
 };
 
+/// Usage of a team crossed `threshold`, see `SubscribeUpdateQuotaWarning`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuotaWarning {
+    pub usage_ratio: f64,
+    pub threshold: f64,
+}
+
+/// Why a connection is closed, sent to the client as the final `Status`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownReason {
+    pub code: Code,
+    pub message: Arc<str>,
+}
+
+impl ShutdownReason {
+    pub fn new(code: Code, message: impl Into<Arc<str>>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn quota_exceeded() -> Self {
+        Self::new(Code::ResourceExhausted, "quota exceeded")
+    }
+
+    pub fn to_status(&self) -> Status {
+        Status::new(self.code, format!("connection closed: {}", self.message))
+    }
+}
+
+/// Latest signal for a connection
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionSignal {
+    Active,
+    QuotaWarning(QuotaWarning),
+    Shutdown(ShutdownReason),
+}
+
+impl ConnectionSignal {
+    pub const fn is_shutdown(&self) -> bool {
+        matches!(self, Self::Shutdown(_))
+    }
+}
+
+/// Metadata of a connected client
 #[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub client_id: usize,
+    pub team_id: String,
+    pub app_id: String,
+    pub endpoint: String,
+    pub network: String,
+    pub connected_at: SystemTime,
+}
+
+#[derive(Debug)]
+struct ConnectionEntry {
+    info: Arc<ConnectionInfo>,
+    sender: watch::Sender<ConnectionSignal>,
+}
 
+/// Maximum number of concurrent streams, `None` is unlimited
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StreamLimits {
+    pub per_team: Option<usize>,
+    pub per_app: Option<usize>,
+}
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum StreamLimitExceeded {
+    #[error("too many streams for team, limit is {0}")]
+    Team(usize),
+    #[error("too many streams for app, limit is {0}")]
+    App(usize),
+}
+
+impl StreamLimitExceeded {
+    pub const fn limit_name(&self) -> &'static str {
+        match self {
+            Self::Team(_) => "team",
+            Self::App(_) => "app",
+        }
+    }
+}
+
+/// Client ids of a team by app
+type TeamApps = BTreeMap<String, BTreeSet<usize>>;
+
+/// Tracks connections by team, app and client id, so a single connection,
+/// an app or a whole team can be warned or shut down
+#[derive(Debug, Default)]
 pub struct ConnectionManager {
-    pub connections: Arc<DashMap<String, watch::Sender<bool>>>,
+    connections: DashMap<usize, ConnectionEntry>,
+    teams: DashMap<String, TeamApps>,
+    /// Last warning of every team in the current period, replayed to new connections
+    quota_warnings: DashMap<String, QuotaWarning>,
 }
 
 impl ConnectionManager {
     pub fn new() -> Self {
-        Self {
-            connections: Arc::new(DashMap::new()),
+        Self::default()
+    }
+
+    /// Registers a new connection if the team and app are below their stream limits
+    /// and returns a token that cleans up automatically when dropped.
+    pub fn register_connection(
+        self: &Arc<Self>,
+        info: ConnectionInfo,
+        limits: StreamLimits,
+    ) -> Result<ConnectionToken, StreamLimitExceeded> {
+        let client_id = info.client_id;
+        let info = Arc::new(info);
+        let registered = {
+            // the team entry stays locked, so concurrent streams can't exceed the limits
+            // and a team shutdown or warning sees either no client id or the connection
+            let mut apps = self.teams.entry(info.team_id.clone()).or_default();
+            let team_streams: usize = apps.values().map(BTreeSet::len).sum();
+            let app_streams = apps.get(&info.app_id).map_or(0, BTreeSet::len);
+            match (limits.per_team, limits.per_app) {
+                (Some(limit), _) if team_streams >= limit => Err(StreamLimitExceeded::Team(limit)),
+                (_, Some(limit)) if app_streams >= limit => Err(StreamLimitExceeded::App(limit)),
+                _ => {
+                    let signal = self
+                        .quota_warning(&info.team_id)
+                        .map(ConnectionSignal::QuotaWarning)
+                        .unwrap_or(ConnectionSignal::Active);
+                    let (sender, receiver) = watch::channel(signal);
+                    self.connections.insert(
+                        client_id,
+                        ConnectionEntry {
+                            info: Arc::clone(&info),
+                            sender,
+                        },
+                    );
+                    apps.entry(info.app_id.clone())
+                        .or_default()
+                        .insert(client_id);
+                    TEAM_STREAMS
+                        .with_label_values(&[&info.team_id])
+                        .set((team_streams + 1) as i64);
+                    Ok(receiver)
+                }
+            }
+        };
+        let receiver = match registered {
+            Ok(receiver) => receiver,
+            Err(error) => {
+                self.remove_team_if_empty(&info.team_id);
+                STREAMS_REJECTED
+                    .with_label_values(&[error.limit_name()])
+                    .inc();
+                return Err(error);
+            }
+        };
+
+        Ok(ConnectionToken::new(info, Arc::clone(self), receiver))
+    }
+
+    pub(crate) fn unregister_connection(&self, client_id: usize) {
+        let Some((_, entry)) = self.connections.remove(&client_id) else {
+            return;
+        };
+        let ConnectionInfo {
+            team_id, app_id, ..
+        } = entry.info.as_ref();
+        // the shard guard has to be released before `remove_if` locks it again
+        if let Some(mut apps) = self.teams.get_mut(team_id) {
+            if let Some(clients) = apps.get_mut(app_id) {
+                clients.remove(&client_id);
+                if clients.is_empty() {
+                    apps.remove(app_id);
+                }
+            }
+            // the series of a team without streams is dropped by `remove_team_if_empty`,
+            // so the gauge only has labels of connected teams
+            let team_streams: usize = apps.values().map(BTreeSet::len).sum();
+            if team_streams > 0 {
+                TEAM_STREAMS
+                    .with_label_values(&[team_id])
+                    .set(team_streams as i64);
+            }
         }
+        self.remove_team_if_empty(team_id);
     }
 
-    /// Registers a new or existing connection and returns a token
-    /// that cleans up automatically when dropped.
-    pub fn register_team(self: Arc<Self>, team_id: String) -> ConnectionToken {
-        let sender = match self.connections.entry(team_id.clone()) {
-            Entry::Occupied(e) => e.get().clone(),
-            Entry::Vacant(e) => {
-                let (tx, _) = watch::channel(false); // false = not shutdown
-                e.insert(tx.clone());
-                tx
+    fn remove_team_if_empty(&self, team_id: &str) {
+        // the gauge is removed under the entry lock, so a new stream can't race with it
+        self.teams.remove_if(team_id, |_, apps| {
+            let empty = apps.is_empty();
+            if empty {
+                let _ = TEAM_STREAMS.remove_label_values(&[team_id]);
             }
+            empty
+        });
+    }
+
+    fn client_ids(&self, team_id: &str, app_id: Option<&str>) -> Vec<usize> {
+        let Some(apps) = self.teams.get(team_id) else {
+            return vec![];
         };
+        match app_id {
+            Some(app_id) => apps
+                .get(app_id)
+                .map(|clients| clients.iter().copied().collect())
+                .unwrap_or_default(),
+            None => apps.values().flatten().copied().collect(),
+        }
+    }
+
+    /// Sends a shutdown signal to the given connections, returns how many were signalled
+    fn shutdown(
+        &self,
+        client_ids: impl IntoIterator<Item = usize>,
+        reason: &ShutdownReason,
+    ) -> usize {
+        client_ids
+            .into_iter()
+            .filter(|client_id| {
+                self.connections.get(client_id).is_some_and(|entry| {
+                    entry.sender.send_if_modified(|signal| {
+                        if signal.is_shutdown() {
+                            return false;
+                        }
+                        *signal = ConnectionSignal::Shutdown(reason.clone());
+                        true
+                    })
+                })
+            })
+            .count()
+    }
+
+    /// Sends a shutdown signal to all connections of this team
+    pub fn shutdown_team(&self, team_id: &str, reason: ShutdownReason) -> usize {
+        self.shutdown(self.client_ids(team_id, None), &reason)
+    }
+
+    /// Sends a shutdown signal to all connections of an app of this team
+    pub fn shutdown_app(&self, team_id: &str, app_id: &str, reason: ShutdownReason) -> usize {
+        self.shutdown(self.client_ids(team_id, Some(app_id)), &reason)
+    }
 
-        let receiver = sender.subscribe();
-        ConnectionToken::new(team_id, self.clone(), sender, receiver)
+    pub fn shutdown_connection(&self, client_id: usize, reason: ShutdownReason) -> bool {
+        self.shutdown([client_id], &reason) > 0
     }
 
-    /// Sends a shutdown signal to all listeners of this team
-    pub fn shutdown_client(&self, team_id: &str) {
-        if let Some(sender) = self.connections.get(team_id) {
-            let _ = sender.send(true); // true = shutdown
+    /// Sends a quota warning to all connections of this team, unless they are shutting down
+    pub fn warn_team(&self, team_id: &str, warning: QuotaWarning) {
+        self.quota_warnings.insert(team_id.to_owned(), warning);
+        for client_id in self.client_ids(team_id, None) {
+            if let Some(entry) = self.connections.get(&client_id) {
+                entry.sender.send_if_modified(|signal| {
+                    if signal.is_shutdown() {
+                        return false;
+                    }
+                    *signal = ConnectionSignal::QuotaWarning(warning);
+                    true
+                });
+            }
         }
     }
 
+    pub fn quota_warning(&self, team_id: &str) -> Option<QuotaWarning> {
+        self.quota_warnings.get(team_id).map(|warning| *warning)
+    }
+
+    pub fn clear_quota_warnings(&self) {
+        self.quota_warnings.clear();
+    }
+
     pub fn list_active_teams(&self) -> Vec<String> {
-        self.connections.iter().map(|e| e.key().clone()).collect()
+        self.teams.iter().map(|e| e.key().clone()).collect()
+    }
+
+    /// Connections of a team, or all connections, ordered by client id
+    pub fn list_connections(&self, team_id: Option<&str>) -> Vec<Arc<ConnectionInfo>> {
+        let mut connections: Vec<Arc<ConnectionInfo>> = self
+            .connections
+            .iter()
+            .filter(|entry| team_id.is_none_or(|team_id| entry.info.team_id == team_id))
+            .map(|entry| Arc::clone(&entry.info))
+            .collect();
+        connections.sort_by_key(|info| info.client_id);
+        connections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        prometheus::core::Collector,
+        std::sync::atomic::{AtomicBool, Ordering},
+    };
+
+    fn info(client_id: usize, team_id: &str, app_id: &str) -> ConnectionInfo {
+        ConnectionInfo {
+            client_id,
+            team_id: team_id.to_owned(),
+            app_id: app_id.to_owned(),
+            endpoint: String::new(),
+            network: "SOLANA_MAINNET".to_owned(),
+            connected_at: SystemTime::now(),
+        }
+    }
+
+    fn team_streams(team_id: &str) -> Option<i64> {
+        TEAM_STREAMS.collect()[0]
+            .get_metric()
+            .iter()
+            .find(|metric| metric.get_label()[0].get_value() == team_id)
+            .map(|metric| metric.get_gauge().get_value() as i64)
+    }
+
+    #[test]
+    fn test_stream_limits() {
+        let manager = Arc::new(ConnectionManager::new());
+        let limits = StreamLimits {
+            per_team: Some(2),
+            per_app: Some(1),
+        };
+
+        let _token1 = manager
+            .register_connection(info(1, "limits", "app1"), limits)
+            .unwrap();
+        assert_eq!(
+            manager
+                .register_connection(info(2, "limits", "app1"), limits)
+                .err(),
+            Some(StreamLimitExceeded::App(1))
+        );
+        let _token3 = manager
+            .register_connection(info(3, "limits", "app2"), limits)
+            .unwrap();
+        assert_eq!(
+            manager
+                .register_connection(info(4, "limits", "app3"), limits)
+                .err(),
+            Some(StreamLimitExceeded::Team(2))
+        );
+        assert_eq!(manager.list_connections(Some("limits")).len(), 2);
+    }
+
+    #[test]
+    fn test_team_streams_gauge_removed() {
+        let manager = Arc::new(ConnectionManager::new());
+        let limits = StreamLimits {
+            per_team: Some(0),
+            per_app: None,
+        };
+        assert!(manager
+            .register_connection(info(1, "gauge-rejected", "app"), limits)
+            .is_err());
+        assert_eq!(team_streams("gauge-rejected"), None);
+        assert!(manager.list_active_teams().is_empty());
+
+        let limits = StreamLimits {
+            per_team: None,
+            per_app: None,
+        };
+        let token1 = manager
+            .register_connection(info(2, "gauge", "app"), limits)
+            .unwrap();
+        let token2 = manager
+            .register_connection(info(3, "gauge", "app"), limits)
+            .unwrap();
+        assert_eq!(team_streams("gauge"), Some(2));
+        drop(token1);
+        assert_eq!(team_streams("gauge"), Some(1));
+        drop(token2);
+        assert_eq!(team_streams("gauge"), None);
+        assert!(manager.list_active_teams().is_empty());
+    }
+
+    #[test]
+    fn test_shutdown_team() {
+        let manager = Arc::new(ConnectionManager::new());
+        let limits = StreamLimits {
+            per_team: None,
+            per_app: None,
+        };
+        let mut token1 = manager
+            .register_connection(info(1, "shutdown", "app1"), limits)
+            .unwrap();
+        let mut token2 = manager
+            .register_connection(info(2, "shutdown", "app2"), limits)
+            .unwrap();
+        let mut other = manager
+            .register_connection(info(3, "other", "app1"), limits)
+            .unwrap();
+
+        let reason = ShutdownReason::new(Code::ResourceExhausted, "quota exceeded");
+        assert_eq!(manager.shutdown_app("shutdown", "app2", reason.clone()), 1);
+        assert_eq!(manager.shutdown_team("shutdown", reason.clone()), 1);
+        // already shutting down
+        assert_eq!(manager.shutdown_team("shutdown", reason), 0);
+        assert!(token1.shutdown_rx().borrow().is_shutdown());
+        assert!(token2.shutdown_rx().borrow().is_shutdown());
+        assert!(!other.shutdown_rx().borrow().is_shutdown());
+    }
+
+    #[test]
+    fn test_register_during_shutdown_team() {
+        let manager = Arc::new(ConnectionManager::new());
+        let reason = ShutdownReason::new(Code::ResourceExhausted, "quota exceeded");
+        let registered = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            let registering = scope.spawn(|| {
+                let tokens = (0..2000)
+                    .map(|client_id| {
+                        manager
+                            .register_connection(
+                                info(client_id, "race", "app"),
+                                StreamLimits::default(),
+                            )
+                            .unwrap()
+                    })
+                    .collect::<Vec<_>>();
+                registered.store(true, Ordering::Relaxed);
+                tokens
+            });
+            while !registered.load(Ordering::Relaxed) {
+                let client_ids = manager.client_ids("race", None);
+                manager.shutdown_team("race", reason.clone());
+                // every stream visible to the shutdown got the signal
+                for client_id in client_ids {
+                    let entry = manager.connections.get(&client_id);
+                    assert!(entry.is_some_and(|entry| entry.sender.borrow().is_shutdown()));
+                }
+            }
+            // streams are unregistered only after the loop, once the tokens are dropped
+            drop(registering.join().unwrap());
+        });
     }
 }
@@ -1,42 +1,43 @@
 use {
-    crate::user_connection::connection_manager::ConnectionManager, log::info, std::sync::Arc,
+    crate::user_connection::connection_manager::{
+        ConnectionInfo, ConnectionManager, ConnectionSignal,
+    },
+    log::info,
+    std::sync::Arc,
     tokio::sync::watch,
 };
 
-#[derive(Clone)]
 pub struct ConnectionToken {
-    team_id: String,
+    info: Arc<ConnectionInfo>,
     manager: Arc<ConnectionManager>,
-    sender: watch::Sender<bool>,
-    receiver: Option<watch::Receiver<bool>>,
+    receiver: watch::Receiver<ConnectionSignal>,
 }
 
 impl ConnectionToken {
-    pub fn new(
-        team_id: String,
+    pub const fn new(
+        info: Arc<ConnectionInfo>,
         manager: Arc<ConnectionManager>,
-        sender: watch::Sender<bool>,
-        receiver: watch::Receiver<bool>,
+        receiver: watch::Receiver<ConnectionSignal>,
     ) -> Self {
         Self {
-            team_id,
+            info,
             manager,
-            sender,
-            receiver: Some(receiver),
+            receiver,
         }
     }
 
-    pub fn shutdown_rx(&mut self) -> &mut watch::Receiver<bool> {
-        self.receiver.as_mut().expect("Receiver already taken")
+    pub const fn info(&self) -> &Arc<ConnectionInfo> {
+        &self.info
+    }
+
+    pub const fn shutdown_rx(&mut self) -> &mut watch::Receiver<ConnectionSignal> {
+        &mut self.receiver
     }
 }
 
 impl Drop for ConnectionToken {
     fn drop(&mut self) {
-        // If only one receiver left (i.e. this one), remove from DashMap
-        if self.sender.receiver_count() <= 1 {
-            info!("Cleaning up entry for team_id: {}", self.team_id);
-            self.manager.connections.remove(&self.team_id);
-        }
+        info!("Cleaning up entry for client #{}", self.info.client_id);
+        self.manager.unregister_connection(self.info.client_id);
     }
 }
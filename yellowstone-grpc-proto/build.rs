@@ -8,6 +8,9 @@ fn main() -> anyhow::Result<()> {
 
     // build protos
     tonic_build::configure().compile_protos(&["proto/geyser.proto"], &["proto"])?;
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/geyser-admin.proto"], &["proto"])?;
 
     // build protos without tonic (wasm)
     let out_dir = env::var("OUT_DIR").expect("OUT_DIR not found");
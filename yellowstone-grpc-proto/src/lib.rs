@@ -10,6 +10,14 @@ pub mod geyser {
     include!(concat!(env!("OUT_DIR"), "/no-tonic/geyser.rs"));
 }
 
+#[cfg(feature = "tonic")]
+pub mod admin {
+    #![allow(clippy::clone_on_ref_ptr)]
+    #![allow(clippy::missing_const_for_fn)]
+
+    include!(concat!(env!("OUT_DIR"), "/geyser.admin.rs"));
+}
+
 pub mod solana {
     #![allow(clippy::missing_const_for_fn)]
 
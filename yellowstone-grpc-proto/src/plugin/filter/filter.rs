@@ -33,6 +33,7 @@ use {
     base64::{engine::general_purpose::STANDARD as base64_engine, Engine},
     bytes::buf::BufMut,
     prost::encoding::{encode_key, encode_varint, WireType},
+    serde::{ser::SerializeMap, Serialize, Serializer},
     solana_sdk::{
         pubkey::{ParsePubkeyError, Pubkey},
         signature::{ParseSignatureError, Signature},
@@ -98,7 +99,8 @@ macro_rules! filtered_updates_once_ref {
     }};
 }
 
-#[derive(Debug, Clone)]
+/// Serializes with pubkeys, signatures and memcmp data as base58 strings
+#[derive(Debug, Clone, Serialize)]
 pub struct Filter {
     accounts: FilterAccounts,
     slots: FilterSlots,
@@ -259,12 +261,14 @@ impl Filter {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize)]
 struct FilterAccounts {
     nonempty_txn_signature: Vec<(FilterName, Option<bool>)>,
     nonempty_txn_signature_required: HashSet<FilterName>,
+    #[serde(serialize_with = "serialize_pubkey_map")]
     account: HashMap<Pubkey, HashSet<FilterName>>,
     account_required: HashSet<FilterName>,
+    #[serde(serialize_with = "serialize_pubkey_map")]
     owner: HashMap<Pubkey, HashSet<FilterName>>,
     owner_required: HashSet<FilterName>,
     filters: Vec<(FilterName, FilterAccountsState)>,
@@ -355,8 +359,9 @@ impl FilterAccounts {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize)]
 struct FilterAccountsState {
+    #[serde(serialize_with = "serialize_memcmp")]
     memcmp: Vec<(usize, Vec<u8>)>,
     datasize: Option<usize>,
     token_account_state: bool,
@@ -470,7 +475,8 @@ impl FilterAccountsState {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 enum FilterAccountsLamports {
     Eq(u64),
     Ne(u64),
@@ -588,7 +594,7 @@ impl<'a> FilterAccountsMatch<'a> {
     }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, Serialize)]
 struct FilterSlotsInner {
     filter_by_commitment: bool,
     interslot_updates: bool,
@@ -603,7 +609,7 @@ impl FilterSlotsInner {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize)]
 struct FilterSlots {
     filters: HashMap<FilterName, FilterSlotsInner>,
 }
@@ -667,18 +673,23 @@ enum FilterTransactionsType {
     TransactionStatus,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 struct FilterTransactionsInner {
     vote: Option<bool>,
     failed: Option<bool>,
+    #[serde(serialize_with = "serialize_signature")]
     signature: Option<Signature>,
+    #[serde(serialize_with = "serialize_pubkey_set")]
     account_include: HashSet<Pubkey>,
+    #[serde(serialize_with = "serialize_pubkey_set")]
     account_exclude: HashSet<Pubkey>,
+    #[serde(serialize_with = "serialize_pubkey_set")]
     account_required: HashSet<Pubkey>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 struct FilterTransactions {
+    #[serde(skip)]
     filter_type: FilterTransactionsType,
     filters: HashMap<FilterName, FilterTransactionsInner>,
 }
@@ -817,7 +828,7 @@ impl FilterTransactions {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize)]
 struct FilterEntries {
     filters: Vec<FilterName>,
 }
@@ -848,15 +859,16 @@ impl FilterEntries {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 struct FilterBlocksInner {
+    #[serde(serialize_with = "serialize_pubkey_set")]
     account_include: HashSet<Pubkey>,
     include_transactions: Option<bool>,
     include_accounts: Option<bool>,
     include_entries: Option<bool>,
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize)]
 struct FilterBlocks {
     filters: HashMap<FilterName, FilterBlocksInner>,
 }
@@ -979,7 +991,7 @@ impl FilterBlocks {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize)]
 struct FilterBlocksMeta {
     filters: Vec<FilterName>,
 }
@@ -1020,6 +1032,12 @@ impl AsRef<[Range<usize>]> for FilterAccountsDataSlice {
     }
 }
 
+impl Serialize for FilterAccountsDataSlice {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_ref().serialize(serializer)
+    }
+}
+
 impl FilterAccountsDataSlice {
     pub fn new(slices: &[SubscribeRequestAccountsDataSlice], limits: usize) -> FilterResult<Self> {
         FilterLimits::check_max(slices.len(), limits)?;
@@ -1102,6 +1120,47 @@ impl FilterAccountsDataSlice {
     }
 }
 
+fn serialize_pubkey_set<S: Serializer>(
+    pubkeys: &HashSet<Pubkey>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut pubkeys = pubkeys.iter().map(Pubkey::to_string).collect::<Vec<_>>();
+    pubkeys.sort();
+    pubkeys.serialize(serializer)
+}
+
+fn serialize_pubkey_map<S: Serializer>(
+    pubkeys: &HashMap<Pubkey, HashSet<FilterName>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(pubkeys.len()))?;
+    for (pubkey, names) in pubkeys {
+        map.serialize_entry(&pubkey.to_string(), names)?;
+    }
+    map.end()
+}
+
+fn serialize_signature<S: Serializer>(
+    signature: &Option<Signature>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    signature
+        .as_ref()
+        .map(Signature::to_string)
+        .serialize(serializer)
+}
+
+fn serialize_memcmp<S: Serializer>(
+    memcmp: &[(usize, Vec<u8>)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    memcmp
+        .iter()
+        .map(|(offset, data)| (*offset, bs58::encode(data).into_string()))
+        .collect::<Vec<_>>()
+        .serialize(serializer)
+}
+
 #[cfg(test)]
 mod tests {
     use {
@@ -1109,7 +1168,7 @@ mod tests {
         crate::{
             convert_to,
             geyser::{
-                SubscribeRequest, SubscribeRequestFilterAccounts,
+                CommitmentLevel, SubscribeRequest, SubscribeRequestFilterAccounts,
                 SubscribeRequestFilterTransactions,
             },
             plugin::{
@@ -1247,6 +1306,49 @@ mod tests {
         assert!(filter.is_err());
     }
 
+    #[test]
+    fn test_filters_serialize() {
+        let account = Pubkey::new_unique();
+        let mut transactions = HashMap::new();
+        transactions.insert(
+            "serum".to_string(),
+            SubscribeRequestFilterTransactions {
+                vote: Some(false),
+                failed: None,
+                signature: None,
+                account_include: vec![account.to_string()],
+                account_exclude: vec![],
+                account_required: vec![],
+            },
+        );
+
+        let config = SubscribeRequest {
+            transactions,
+            commitment: Some(CommitmentLevel::Confirmed as i32),
+            ..Default::default()
+        };
+        let filter = Filter::new(
+            &config,
+            &FilterLimits::default(),
+            &mut create_filter_names(),
+        )
+        .unwrap();
+        let value = serde_json::to_value(&filter).unwrap();
+        assert_eq!(value["commitment"], "confirmed");
+        assert_eq!(
+            value["transactions"]["filters"]["serum"],
+            serde_json::json!({
+                "vote": false,
+                "failed": null,
+                "signature": null,
+                "account_include": [account.to_string()],
+                "account_exclude": [],
+                "account_required": [],
+            })
+        );
+        assert_eq!(value["accounts_data_slice"], serde_json::json!([]));
+    }
+
     #[test]
     fn test_filters_transaction_empty() {
         let mut transactions = HashMap::new();
@@ -3,9 +3,9 @@ use {
         geyser::{
             subscribe_update::UpdateOneof, SlotStatus as SlotStatusProto, SubscribeUpdate,
             SubscribeUpdateAccount, SubscribeUpdateAccountInfo, SubscribeUpdateBlock,
-            SubscribeUpdateEntry, SubscribeUpdatePing, SubscribeUpdatePong, SubscribeUpdateSlot,
-            SubscribeUpdateTransaction, SubscribeUpdateTransactionInfo,
-            SubscribeUpdateTransactionStatus,
+            SubscribeUpdateEntry, SubscribeUpdatePing, SubscribeUpdatePong,
+            SubscribeUpdateQuotaWarning, SubscribeUpdateSlot, SubscribeUpdateTransaction,
+            SubscribeUpdateTransactionInfo, SubscribeUpdateTransactionStatus,
         },
         plugin::{
             filter::{name::FilterName, FilterAccountsDataSlice},
@@ -232,6 +232,7 @@ impl FilteredUpdate {
             FilteredUpdateOneof::Entry(msg) => {
                 UpdateOneof::Entry(Self::as_subscribe_update_entry(&msg.0))
             }
+            FilteredUpdateOneof::QuotaWarning(msg) => UpdateOneof::QuotaWarning(*msg),
         };
 
         SubscribeUpdate {
@@ -307,6 +308,7 @@ impl FilteredUpdate {
                 let entry = MessageEntry::from_update_oneof(&msg, created_at)?;
                 FilteredUpdateOneof::Entry(FilteredUpdateEntry(Arc::new(entry)))
             }
+            UpdateOneof::QuotaWarning(msg) => FilteredUpdateOneof::QuotaWarning(msg),
         };
 
         Ok(Self {
@@ -330,6 +332,7 @@ pub enum FilteredUpdateOneof {
     Pong(SubscribeUpdatePong),                          // 9
     BlockMeta(Arc<MessageBlockMeta>),                   // 7
     Entry(FilteredUpdateEntry),                         // 8
+    QuotaWarning(SubscribeUpdateQuotaWarning),          // 12
 }
 
 impl FilteredUpdateOneof {
@@ -380,6 +383,13 @@ impl FilteredUpdateOneof {
         Self::Entry(FilteredUpdateEntry(message))
     }
 
+    pub const fn quota_warning(usage_ratio: f64, threshold: f64) -> Self {
+        Self::QuotaWarning(SubscribeUpdateQuotaWarning {
+            usage_ratio,
+            threshold,
+        })
+    }
+
     pub const fn subscription_type(&self) -> &'static str {
         match self {
             FilteredUpdateOneof::Account(_) => "account",
@@ -391,6 +401,7 @@ impl FilteredUpdateOneof {
             FilteredUpdateOneof::Pong(_) => "pong",
             FilteredUpdateOneof::BlockMeta(_) => "blockMeta",
             FilteredUpdateOneof::Entry(_) => "entry",
+            FilteredUpdateOneof::QuotaWarning(_) => "quotaWarning",
         }
     }
 }
@@ -410,6 +421,7 @@ impl prost::Message for FilteredUpdateOneof {
             Self::Pong(msg) => message::encode(9u32, msg, buf),
             Self::BlockMeta(msg) => message::encode(7u32, &msg.block_meta, buf),
             Self::Entry(msg) => message::encode(8u32, msg, buf),
+            Self::QuotaWarning(msg) => message::encode(12u32, msg, buf),
         }
     }
 
@@ -424,6 +436,7 @@ impl prost::Message for FilteredUpdateOneof {
             Self::Pong(msg) => message::encoded_len(9u32, msg),
             Self::BlockMeta(msg) => message::encoded_len(7u32, &msg.block_meta),
             Self::Entry(msg) => message::encoded_len(8u32, msg),
+            Self::QuotaWarning(msg) => message::encoded_len(12u32, msg),
         }
     }
 
@@ -1343,6 +1356,12 @@ pub mod tests {
         encode_decode_cmp(&["123"], FilteredUpdateOneof::pong(42));
     }
 
+    #[test]
+    fn test_message_quota_warning() {
+        encode_decode_cmp(&["123"], FilteredUpdateOneof::quota_warning(0.0, 0.0));
+        encode_decode_cmp(&["123"], FilteredUpdateOneof::quota_warning(0.85, 0.8));
+    }
+
     #[test]
     fn test_message_blockmeta() {
         for block_meta in load_predefined_blockmeta() {
@@ -1,9 +1,12 @@
-use std::{
-    borrow::Borrow,
-    collections::HashSet,
-    ops::Deref,
-    sync::Arc,
-    time::{Duration, Instant},
+use {
+    serde::{Serialize, Serializer},
+    std::{
+        borrow::Borrow,
+        collections::HashSet,
+        ops::Deref,
+        sync::Arc,
+        time::{Duration, Instant},
+    },
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -39,6 +42,12 @@ impl Borrow<str> for FilterName {
     }
 }
 
+impl Serialize for FilterName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
 impl FilterName {
     pub fn new(name: impl Into<String>) -> Self {
         Self(Arc::new(name.into()))
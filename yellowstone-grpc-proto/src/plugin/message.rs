@@ -14,6 +14,7 @@ use {
         SlotStatus as GeyserSlotStatus,
     },
     prost_types::Timestamp,
+    serde::Serialize,
     solana_sdk::{
         clock::Slot,
         hash::{Hash, HASH_BYTES},
@@ -30,7 +31,8 @@ use {
 
 type FromUpdateOneofResult<T> = Result<T, &'static str>;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum CommitmentLevel {
     Processed,
     Confirmed,
@@ -577,6 +579,7 @@ impl Message {
             }
             UpdateOneof::Ping(_) => return Err("Ping message is not supported"),
             UpdateOneof::Pong(_) => return Err("Pong message is not supported"),
+            UpdateOneof::QuotaWarning(_) => return Err("QuotaWarning message is not supported"),
             UpdateOneof::BlockMeta(msg) => Self::BlockMeta(Arc::new(
                 MessageBlockMeta::from_update_oneof(msg, created_at),
             )),